@@ -0,0 +1,124 @@
+use std::time::Duration;
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::{timeout_at, Instant};
+use crate::{CompileReport, Compiler, CompilerError};
+
+/// What happened to one of the projects of `compile_all_within`
+#[derive(Debug)]
+pub enum BatchOutcome {
+    /// The project compiled
+    Succeeded(CompileReport),
+    /// The project failed to compile, which doesn't stop the others
+    Failed(CompilerError),
+    /// The project was still compiling when the deadline passed, so its compiler was killed
+    Cancelled,
+    /// The deadline passed before the project was started
+    NotStarted,
+}
+
+/// The outcomes of `compile_all_within`, in the order the compilers were given
+#[derive(Debug)]
+pub struct BatchReport {
+    pub outcomes: Vec<BatchOutcome>,
+}
+
+impl BatchReport {
+    /// Whether every project compiled
+    pub fn all_succeeded(&self) -> bool {
+        self.outcomes
+            .iter()
+            .all(|outcome| matches!(outcome, BatchOutcome::Succeeded(_)))
+    }
+}
+
+/// Compiles every project, at most `concurrency` at a time, within an overall time budget
+///
+/// A failed project doesn't stop the others. Once `deadline` has passed no other project is
+/// started, and the compilers still running are killed, so a few pathological documents
+/// can't use up a time-boxed CI stage. A cancelled project may leave its intermediate files
+/// in its `out_dir`.
+pub async fn compile_all_within(compilers: Vec<Compiler>, concurrency: usize, deadline: Duration) -> BatchReport {
+    let deadline = Instant::now() + deadline;
+    let mut outcomes: Vec<BatchOutcome> = compilers.iter().map(|_| BatchOutcome::NotStarted).collect();
+    let mut pending = compilers.into_iter().enumerate();
+    let mut tasks = JoinSet::new();
+    let run = async {
+        loop {
+            while tasks.len() < concurrency.max(1) {
+                let Some((i, compiler)) = pending.next() else {
+                    break;
+                };
+                // stays `Cancelled` unless the compile finishes before the deadline
+                outcomes[i] = BatchOutcome::Cancelled;
+                tasks.spawn(async move { (i, compiler.compile().await) });
+            }
+            match tasks.join_next().await {
+                Some(joined) => record(&mut outcomes, joined),
+                None => break,
+            }
+        }
+    };
+    let _ = timeout_at(deadline, run).await;
+    // a compile that finished as the deadline passed isn't cancelled
+    while let Some(joined) = tasks.try_join_next() {
+        record(&mut outcomes, joined);
+    }
+    // the compile commands are killed when their tasks are dropped
+    tasks.shutdown().await;
+    BatchReport { outcomes }
+}
+
+/// Records the outcome of a joined compile task, resuming its panic if it panicked
+fn record(outcomes: &mut [BatchOutcome], joined: Result<(usize, crate::Result<CompileReport>), JoinError>) {
+    let (i, result) = joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+    outcomes[i] = match result {
+        Ok(report) => BatchOutcome::Succeeded(report),
+        Err(e) => BatchOutcome::Failed(e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::tests::script;
+    use crate::unique_temp_dir;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn compile_all_within_reports_every_outcome() {
+        let dir = unique_temp_dir("test-", "batch");
+        std::fs::create_dir_all(&dir).unwrap();
+        let fast = script(&dir, "fasttex", "exit 0");
+        let slow = script(&dir, "slowtex", "exec sleep 30");
+        let failing = script(&dir, "failtex", "exit 1");
+        // the fast and failing compiles free their slot, the two slow ones hold both past the deadline
+        let compilers = [&fast, &slow, &failing, &slow, &fast]
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let mut compiler = Compiler::new("doc");
+                compiler.compiler = path.clone();
+                compiler.quiet = true;
+                compiler.out_dir = dir.join(format!("out{i}"));
+                compiler
+            })
+            .collect();
+        let report = compile_all_within(compilers, 2, Duration::from_millis(500)).await;
+        assert!(
+            matches!(
+                report.outcomes[..],
+                [
+                    BatchOutcome::Succeeded(_),
+                    BatchOutcome::Cancelled,
+                    BatchOutcome::Failed(CompilerError::CompileFailed { .. }),
+                    BatchOutcome::Cancelled,
+                    BatchOutcome::NotStarted,
+                ]
+            ),
+            "{:?}",
+            report.outcomes
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
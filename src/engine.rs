@@ -0,0 +1,127 @@
+use std::path::Path;
+use crate::find_executable;
+
+/// The LaTeX engines TexCreate knows the command line of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engine {
+    Pdflatex,
+    Xelatex,
+    Lualatex,
+    /// Plain `latex`, which produces DVI output
+    Latex,
+    /// Tectonic, which reruns itself and the bibliography tool as needed, and has its own
+    /// command line, `tectonic -o out report.tex`
+    Tectonic,
+    /// Any other compiler, which is assumed to accept the same arguments as `pdflatex`
+    Other(String),
+}
+
+impl Engine {
+    /// The engines [`Engine::detect`] probes for, in order
+    ///
+    /// `lualatex` comes first as it handles Unicode and system fonts, then `xelatex`, which
+    /// does too, and `pdflatex`, which every distribution ships.
+    pub const DETECT_ORDER: &'static [Engine] = &[Self::Lualatex, Self::Xelatex, Self::Pdflatex];
+
+    /// The first of the [`DETECT_ORDER`](Self::DETECT_ORDER) engines that's installed, searched
+    /// in `PATH`
+    pub async fn detect() -> Option<Self> {
+        Self::detect_from(Self::DETECT_ORDER).await
+    }
+    /// The first of `preference` that's installed, searched in `PATH`
+    pub async fn detect_from(preference: &[Engine]) -> Option<Self> {
+        for engine in preference {
+            if find_executable(engine.name()).await.is_some() {
+                return Some(engine.clone());
+            }
+        }
+        None
+    }
+    /// Gets the engine from the name or path of its binary, such as `xelatex` or `/usr/bin/xelatex`
+    pub fn from_name(name: &str) -> Self {
+        let stem = Path::new(name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(name);
+        match stem {
+            "pdflatex" => Self::Pdflatex,
+            "xelatex" => Self::Xelatex,
+            "lualatex" => Self::Lualatex,
+            "latex" => Self::Latex,
+            "tectonic" => Self::Tectonic,
+            _ => Self::Other(name.to_string()),
+        }
+    }
+    /// The name of the engine's binary
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Pdflatex => "pdflatex",
+            Self::Xelatex => "xelatex",
+            Self::Lualatex => "lualatex",
+            Self::Latex => "latex",
+            Self::Tectonic => "tectonic",
+            Self::Other(name) => name,
+        }
+    }
+    /// Whether the engine can produce DVI output
+    ///
+    /// `xelatex -no-pdf` produces XDV rather than DVI, so it isn't counted.
+    pub fn supports_dvi(&self) -> bool {
+        matches!(self, Self::Pdflatex | Self::Lualatex | Self::Latex)
+    }
+    /// The arguments that make the engine write to `dir`, `-output-directory=dir`, or
+    /// `-o dir` for `tectonic`
+    pub fn output_directory_args(&self, dir: &Path) -> Vec<String> {
+        match self {
+            Self::Tectonic => vec!["-o".to_string(), dir.display().to_string()],
+            _ => vec![format!("-output-directory={}", dir.display())],
+        }
+    }
+    /// Whether the engine runs as many passes as the document needs, and the bibliography
+    /// tool, by itself, so `passes` is ignored and nothing can run between its passes
+    pub fn runs_own_passes(&self) -> bool {
+        matches!(self, Self::Tectonic)
+    }
+    /// Whether the engine accepts `flag`, which only rules out the `pdflatex` flags that
+    /// `tectonic` doesn't have, such as those of the [`platform_default_flags`](crate::platform_default_flags)
+    ///
+    /// `-shell-escape` is accepted, as it's passed to `tectonic` as `-Z shell-escape`, like `shell_escape`.
+    pub fn accepts_flag(&self, flag: &str) -> bool {
+        /// The `pdflatex` flags `tectonic` doesn't have, without their dashes
+        const TECTONIC_UNKNOWN: &[&str] = &[
+            "interaction", "file-line-error", "c-style-errors", "halt-on-error", "no-shell-escape",
+            "shell-restricted", "disable-write18", "output-directory", "jobname", "synctex", "draftmode",
+            "no-pdf", "output-comment", "output-format", "recorder", "8bit",
+        ];
+        match self {
+            Self::Tectonic => !TECTONIC_UNKNOWN.contains(&crate::flag_name(flag)),
+            _ => true,
+        }
+    }
+    /// The flag that makes the engine skip writing its output, for quicker draft passes
+    ///
+    /// `xelatex` has no draft mode, but `-no-pdf` skips the conversion to PDF instead.
+    pub fn draft_flag(&self) -> Option<&'static str> {
+        match self {
+            Self::Pdflatex | Self::Lualatex | Self::Latex => Some("-draftmode"),
+            Self::Xelatex => Some("-no-pdf"),
+            Self::Tectonic | Self::Other(_) => None,
+        }
+    }
+    /// The TeX code that pins the version of the PDF the engine produces
+    ///
+    /// - `pdflatex` uses `\pdfminorversion`, and `\pdfmajorversion` when the major version isn't 1
+    /// - `lualatex` uses `\pdfvariable majorversion` and `\pdfvariable minorversion`
+    /// - `xelatex` leaves the version to `xdvipdfmx`, and `latex` doesn't produce a PDF,
+    ///   so neither is supported, nor are other engines
+    pub fn pdf_version_primitive(&self, major: u8, minor: u8) -> Option<String> {
+        match self {
+            Self::Pdflatex if major == 1 => Some(format!("\\pdfminorversion={minor}")),
+            Self::Pdflatex => Some(format!("\\pdfmajorversion={major} \\pdfminorversion={minor}")),
+            Self::Lualatex => Some(format!(
+                "\\pdfvariable majorversion={major} \\pdfvariable minorversion={minor}"
+            )),
+            _ => None,
+        }
+    }
+}
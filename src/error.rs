@@ -0,0 +1,87 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// The errors that can occur while configuring or running the compiler
+#[derive(Debug)]
+pub enum CompilerError {
+    /// An I/O error, from reading or writing a file or running a command
+    Io(std::io::Error),
+    /// A command couldn't be started or waited on, with the arguments it was run with
+    Command { argv: Vec<String>, source: std::io::Error },
+    /// `compiler.toml` couldn't be parsed
+    Toml(toml::de::Error),
+    /// An external tool the crate relies on isn't installed
+    ToolNotFound(String),
+    /// The configured compiler couldn't be found, in `PATH` or at the path it names
+    CompilerNotFound(String),
+    /// A source file of the project doesn't exist
+    SourceNotFound(PathBuf),
+    /// `compile_profile` was given a profile that isn't in `profiles`
+    UnknownProfile(String),
+    /// `out_dir` couldn't be created or written to, such as on a read-only volume
+    OutputDirNotWritable { path: PathBuf, source: std::io::Error },
+    /// A file or directory cleaning removes couldn't be removed
+    CleanFailed { path: PathBuf, source: std::io::Error },
+    /// The compiler exited unsuccessfully during the pass `pass`, usually because of a TeX error
+    CompileFailed { pass: u32, status: ExitStatus },
+    /// A command run by the crate, such as a custom step, exited unsuccessfully
+    StepFailed { command: String, status: ExitStatus },
+    /// The configuration is inconsistent, with a description of the problem
+    InvalidConfig(String),
+    /// The engine doesn't support a feature the configuration asks for
+    Unsupported { engine: String, feature: String },
+    /// The engine can't produce the requested output format
+    UnsupportedFormat { engine: String, format: String },
+}
+
+impl Display for CompilerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Command { argv, source } => write!(f, "couldn't run `{}`: {source}", argv.join(" ")),
+            Self::Toml(e) => write!(f, "couldn't parse compiler.toml: {e}"),
+            Self::ToolNotFound(tool) => write!(f, "`{tool}` isn't installed or isn't in PATH"),
+            Self::CompilerNotFound(compiler) => write!(f, "the compiler `{compiler}` couldn't be found"),
+            Self::SourceNotFound(path) => write!(f, "the source file `{}` doesn't exist", path.display()),
+            Self::UnknownProfile(name) => write!(f, "there is no profile named `{name}`"),
+            Self::OutputDirNotWritable { path, source } => {
+                write!(f, "the output directory `{}` isn't writable: {source}", path.display())
+            }
+            Self::CleanFailed { path, source } => write!(f, "couldn't clean `{}`: {source}", path.display()),
+            Self::CompileFailed { pass, status } => write!(f, "the compiler failed during pass {pass} with {status}"),
+            Self::StepFailed { command, status } => write!(f, "`{command}` failed with {status}"),
+            Self::InvalidConfig(reason) => write!(f, "invalid compiler configuration: {reason}"),
+            Self::Unsupported { engine, feature } => write!(f, "`{engine}` doesn't support {feature}"),
+            Self::UnsupportedFormat { engine, format } => write!(f, "`{engine}` can't produce {format}"),
+        }
+    }
+}
+
+impl std::error::Error for CompilerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Command { source, .. } => Some(source),
+            Self::Toml(e) => Some(e),
+            Self::OutputDirNotWritable { source, .. } => Some(source),
+            Self::CleanFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CompilerError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for CompilerError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::Toml(e)
+    }
+}
+
+/// The result type used throughout the crate
+pub type Result<T> = std::result::Result<T, CompilerError>;
@@ -0,0 +1,716 @@
+use std::ops::{Deref, DerefMut};
+use std::path::{Component, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::SystemTime;
+use termcolor::Color::{Green, Yellow};
+use tokio::fs::{OpenOptions, create_dir_all, metadata, read_to_string, remove_file, write};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+use crate::{
+    copy_all, enables_shell_escape, managed_flag, needs_rerun, needs_shell_escape, utc_timestamp, CompileEvent,
+    CompileOverrides, CompileReport, Compiler, CompilerError, CompilerMode, Engine, LogTail, Result, SyncTex,
+    TempDir, Warnings, DEFAULT_OUT_DIR,
+};
+
+/// A compile of a `Compiler` with `CompileOverrides` applied, borrowing both, so a base
+/// configuration can be compiled with different settings without being cloned
+///
+/// The overridden settings shadow the fields of the same name, and everything else is read
+/// from the configuration through `Deref`. Every method that reads one of the overridden
+/// settings lives here, so it can't read the configuration's value by mistake.
+#[derive(Clone, Copy)]
+pub(crate) struct Job<'a> {
+    config: &'a Compiler,
+    flags: &'a [String],
+    pub(crate) passes: u32,
+    draft: bool,
+    clean: bool,
+    shell_escape: bool,
+    // the log followed by `compile_tailing_log`, told when each pass starts and ends
+    log_tail: Option<&'a Mutex<LogTail>>,
+}
+
+impl Deref for Job<'_> {
+    type Target = Compiler;
+
+    fn deref(&self) -> &Compiler {
+        self.config
+    }
+}
+
+impl Compiler {
+    /// A compile of this configuration with `overrides` applied
+    pub(crate) fn job<'a>(&'a self, overrides: &CompileOverrides<'a>) -> Job<'a> {
+        Job {
+            config: self,
+            flags: overrides.flags.unwrap_or(&self.flags),
+            // the engine runs once, and reruns itself as needed
+            passes: if self.engine().runs_own_passes() {
+                1
+            } else {
+                overrides.passes.unwrap_or(self.passes)
+            },
+            draft: overrides.draft.unwrap_or(self.draft),
+            clean: overrides.clean.unwrap_or(self.clean),
+            shell_escape: self.shell_escape,
+            log_tail: None,
+        }
+    }
+}
+
+impl<'a> Job<'a> {
+    /// Tells `tail` when each pass starts and ends, so it follows the log of every pass
+    pub(crate) fn with_log_tail(self, tail: &'a Mutex<LogTail>) -> Self {
+        Job {
+            log_tail: Some(tail),
+            ..self
+        }
+    }
+}
+
+impl Job<'_> {
+    /// [`Compiler::validate`], with the overrides applied
+    pub(crate) fn validate(&self) -> Result<()> {
+        let engine = self.engine();
+        if self.proj_name.is_empty() {
+            return Err(CompilerError::InvalidConfig("`proj_name` is empty".to_string()));
+        }
+        if self.passes == 0 {
+            return Err(CompilerError::InvalidConfig("`passes` must be at least 1".to_string()));
+        }
+        if self.draft && engine.draft_flag().is_none() {
+            return Err(CompilerError::Unsupported {
+                engine: engine.name().to_string(),
+                feature: "draft mode".to_string(),
+            });
+        }
+        for flag in self.flags {
+            if let Some((managed, Some(field))) = managed_flag(flag) {
+                return Err(CompilerError::InvalidConfig(format!(
+                    "`{managed}` is set through `{field}`, remove it from `flags`"
+                )));
+            }
+        }
+        let modes = ["batchmode", "nonstopmode", "scrollmode", "errorstopmode"];
+        if self.interaction_via_source && !modes.contains(&self.interaction_mode()) {
+            return Err(CompilerError::InvalidConfig(format!(
+                "`{}` isn't an interaction mode, expected one of {}",
+                self.interaction_mode(),
+                modes.join(", ")
+            )));
+        }
+        if self.wants_dvi() && !engine.supports_dvi() {
+            return Err(CompilerError::Unsupported {
+                engine: engine.name().to_string(),
+                feature: "DVI output".to_string(),
+            });
+        }
+        if engine.runs_own_passes() && (self.index || !self.custom_steps.is_empty()) {
+            return Err(CompilerError::Unsupported {
+                engine: engine.name().to_string(),
+                feature: "steps between passes, such as `index` or `custom_steps`".to_string(),
+            });
+        }
+        if engine == Engine::Tectonic {
+            // there's no `-jobname` to name the output after the project rather than the entry file
+            if self.pretex().is_some() {
+                return Err(CompilerError::Unsupported {
+                    engine: engine.name().to_string(),
+                    feature: "the TeX code injected before the source, for `preamble`, `metadata` or \
+                              `interaction_via_source`"
+                        .to_string(),
+                });
+            }
+            if self.synctex == SyncTex::Uncompressed {
+                return Err(CompilerError::Unsupported {
+                    engine: engine.name().to_string(),
+                    feature: "uncompressed SyncTeX".to_string(),
+                });
+            }
+        }
+        if self.index && self.passes < 2 {
+            return Err(CompilerError::InvalidConfig(
+                "`index` needs at least 2 passes to use the index it generates".to_string(),
+            ));
+        }
+        for step in &self.custom_steps {
+            if step.command.trim().is_empty() {
+                return Err(CompilerError::InvalidConfig("a custom step has an empty `command`".to_string()));
+            }
+            if step.after_pass == 0 || step.after_pass > self.passes {
+                return Err(CompilerError::InvalidConfig(format!(
+                    "the step `{}` runs after pass {}, but there are {} passes",
+                    step.command, step.after_pass, self.passes
+                )));
+            }
+        }
+        for path in &self.copy_before_compile {
+            // copies mirror the project's layout, so they have to stay inside `out_dir`
+            let relative = path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+            let named = path.components().any(|component| matches!(component, Component::Normal(_)));
+            if !relative || !named {
+                return Err(CompilerError::InvalidConfig(format!(
+                    "`copy_before_compile` entry `{}` must be a relative path inside the project",
+                    path.display()
+                )));
+            }
+        }
+        if let Some((major, minor)) = self.pdf_version {
+            if engine.pdf_version_primitive(major, minor).is_none() {
+                return Err(CompilerError::UnsupportedFormat {
+                    engine: engine.name().to_string(),
+                    format: format!("PDF {major}.{minor}"),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the flags ask the engine for DVI output
+    fn wants_dvi(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|flag| flag.trim_start_matches('-') == "output-format=dvi")
+    }
+
+    /// [`Compiler::output_path`], with the overrides applied
+    pub(crate) fn output_path(&self) -> PathBuf {
+        let ext = if self.wants_dvi() || self.engine() == Engine::Latex {
+            "dvi"
+        } else {
+            "pdf"
+        };
+        self.artifact_path(ext)
+    }
+
+    /// The TeX code injected before the project's source, if any
+    ///
+    /// When `preamble` or `pdf_version` is set, the compiler is given a generated
+    /// `proj_name.texc.tex` entry file, that sets the PDF version, registers the preamble on
+    /// LaTeX's `class/after` hook, and then inputs `proj_name.tex`, with `-jobname` keeping the
+    /// output names unchanged. See [`Engine::pdf_version_primitive`] for the engines that can
+    /// pin the PDF version, others fail with [`CompilerError::UnsupportedFormat`]. Unlike
+    /// `-usepretex`, the entry file works with every engine, but has the following limitations:
+    /// - it requires a LaTeX kernel from 2020-10 or newer, which introduced hooks
+    /// - the preamble runs after the first class finishes loading, so with a class that
+    ///   loads another class, it runs before the outer class is done
+    /// - the main file is the entry file, so tools reading the input file name see it instead
+    pub(crate) fn pretex(&self) -> Option<String> {
+        let mut pretex = String::new();
+        if self.interaction_via_source {
+            pretex.push_str(&format!("\\{}\n", self.interaction_mode()));
+        }
+        if let Some((major, minor)) = self.pdf_version {
+            // has to come before anything is written to the PDF
+            if let Some(primitive) = self.engine().pdf_version_primitive(major, minor) {
+                pretex.push_str(&primitive);
+                pretex.push('\n');
+            }
+        }
+        if let Some(preamble) = &self.preamble {
+            pretex.push_str(&format!("\\AddToHookNext{{class/after}}{{{preamble}}}\n"));
+        }
+        if let Some(metadata) = &self.metadata {
+            let keys = metadata.hypersetup_keys();
+            if !keys.is_empty() {
+                pretex.push_str(&format!("\\AtBeginDocument{{\\hypersetup{{{}}}}}\n", keys.join(",")));
+            }
+        }
+        (!pretex.is_empty()).then_some(pretex)
+    }
+
+    /// The interaction mode of the `-interaction` flag, `nonstopmode` without one
+    ///
+    /// With `interaction_via_source`, the mode's primitive, such as `\batchmode`, starts the
+    /// entry file. Every TeX engine has these primitives, so it's honored even where the
+    /// flag is dropped by a wrapper or ignored, while `pdflatex`, `xelatex` and `lualatex`
+    /// honor the flag itself too, so both agree. It's a file rather than `-usepretex`, which
+    /// only recent TeX Live builds of `pdftex` accept. The primitive only takes effect once
+    /// it's read, so the engine's banner is still printed.
+    fn interaction_mode(&self) -> &str {
+        self.flags
+            .iter()
+            .rev()
+            .find_map(|flag| match flag.trim_start_matches('-').split_once('=') {
+                Some(("interaction", mode)) => Some(mode),
+                _ => None,
+            })
+            .unwrap_or("nonstopmode")
+    }
+
+    /// [`Compiler::command_args`], with the overrides applied
+    ///
+    /// With `output_comment`, such as a git SHA or a build number, `-output-comment` writes
+    /// it in the preamble of DVI and XDV output instead of the date, truncated to 255 bytes.
+    /// PDFs have no such comment, so it's ignored for them, and `metadata` stamps a PDF
+    /// instead. Replacing the date makes the DVI reproducible as long as the comment stays
+    /// the same: a git SHA keeps the rebuilds of a commit identical, while a build number
+    /// makes each build differ, so the byte comparison of `compile_and_diff` reports them.
+    pub(crate) fn command_args(&self) -> Vec<String> {
+        if self.engine() == Engine::Tectonic {
+            return self.tectonic_args();
+        }
+        let mut args = self.engine().output_directory_args(&self.out_dir);
+        args.reserve(self.flags.len() + 2);
+        if self.draft {
+            args.extend(self.engine().draft_flag().map(str::to_string));
+        }
+        args.extend(self.synctex.flag().map(str::to_string));
+        if let Some(comment) = &self.output_comment {
+            args.push(format!("-output-comment={comment}"));
+        }
+        if self.shell_escape {
+            args.push(self.distro.shell_escape_flag().to_string());
+        }
+        args.extend(self.flags.iter().cloned());
+        if self.pretex().is_some() {
+            // keep the output named after the project rather than the entry file
+            args.push(format!("-jobname={}", &self.proj_name));
+            args.push(self.entry_file().display().to_string());
+        } else {
+            args.push(self.proj_name.clone());
+        }
+        args
+    }
+
+    /// The arguments given to `tectonic`, which has its own command line
+    ///
+    /// The flags it doesn't accept are left out, `-shell-escape` becomes `-Z shell-escape`,
+    /// and the log and the intermediate files are kept, so the report can count the log's
+    /// warnings and cleaning finds the `clean_exts` files it expects. There's no `-jobname`, so
+    /// the source is given with its extension and `output_comment`, which only DVI output
+    /// has, is left out too.
+    fn tectonic_args(&self) -> Vec<String> {
+        let mut args = self.engine().output_directory_args(&self.out_dir);
+        args.extend(["--keep-logs".to_string(), "--keep-intermediates".to_string()]);
+        if self.synctex != SyncTex::Off {
+            args.push("--synctex".to_string());
+        }
+        if self.shell_escape {
+            args.extend(["-Z".to_string(), "shell-escape".to_string()]);
+        }
+        for flag in self.flags {
+            if enables_shell_escape(std::slice::from_ref(flag)) {
+                args.extend(["-Z".to_string(), "shell-escape".to_string()]);
+            } else if self.engine().accepts_flag(flag) {
+                args.push(flag.clone());
+            }
+        }
+        args.push(self.source_path().display().to_string());
+        args
+    }
+
+    /// [`Compiler::argv`], with the overrides applied
+    pub(crate) fn argv(&self) -> Vec<String> {
+        let mut argv = self.sandbox.as_ref().map(|sandbox| sandbox.wrapper.clone()).unwrap_or_default();
+        argv.extend(self.wrapper.iter().cloned());
+        argv.push(self.compiler.clone());
+        argv.extend(self.command_args());
+        argv
+    }
+
+    /// Builds the compile command from `argv`, to be spawned with [`ChildGuard::spawn`]
+    fn command(&self) -> Command {
+        let argv = self.argv();
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.restrict(&mut command);
+        }
+        if let Some(work_dir) = &self.work_dir {
+            command.current_dir(work_dir);
+        }
+        command
+    }
+
+    /// Wraps an error from running the compiler with the command line that was run
+    fn command_error(&self, source: std::io::Error) -> CompilerError {
+        CompilerError::Command {
+            argv: self.argv(),
+            source,
+        }
+    }
+
+    async fn output(&self) -> Result<std::process::Output> {
+        let mut command = self.command();
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = ChildGuard::spawn(&mut command).map_err(|e| self.command_error(e))?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let (mut output, mut errors) = (vec![], vec![]);
+        let (status, _, _) = tokio::try_join!(
+            child.wait(),
+            stdout.read_to_end(&mut output),
+            stderr.read_to_end(&mut errors),
+        )
+        .map_err(|e| self.command_error(e))?;
+        Ok(std::process::Output {
+            status,
+            stdout: output,
+            stderr: errors,
+        })
+    }
+
+    async fn spawn(&self) -> Result<ExitStatus> {
+        let mut child = ChildGuard::spawn(&mut self.command()).map_err(|e| self.command_error(e))?;
+        child.wait().await.map_err(|e| self.command_error(e))
+    }
+
+    /// Appends the output of a compile to `log_file`, with a header saying when and how it ran
+    ///
+    /// The file only grows, so rotating it is left to tools like `logrotate`. A log that can't
+    /// be written only warns, so it never fails the compile itself.
+    async fn append_log_file(&self, transcript: &str, outcome: &Result<()>) {
+        let Some(path) = &self.log_file else {
+            return;
+        };
+        let mut entry = format!("==> {} {}\n", utc_timestamp(SystemTime::now()), self.argv().join(" "));
+        entry.push_str(transcript);
+        if !transcript.is_empty() && !transcript.ends_with('\n') {
+            entry.push('\n');
+        }
+        match outcome {
+            Ok(()) => entry.push_str("<== done\n\n"),
+            Err(e) => entry.push_str(&format!("<== failed: {e}\n\n")),
+        }
+        let written = async {
+            let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+            file.write_all(entry.as_bytes()).await?;
+            // the write only finishes in the background otherwise
+            file.flush().await
+        };
+        if let Err(e) = written.await {
+            cprint!(self, Yellow, "Couldn't write the log file `{}`: {}", path.display(), e);
+        }
+    }
+
+    /// Runs the compiler with its output piped, sending every line it prints as an event
+    ///
+    /// The lines are also added to `transcript` when there's a `log_file` to write it to.
+    async fn stream(&self, events: &Sender<CompileEvent>, transcript: &mut String) -> Result<ExitStatus> {
+        let mut command = self.command();
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = ChildGuard::spawn(&mut command).map_err(|e| self.command_error(e))?;
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+        let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped")).lines();
+        let (mut stdout_done, mut stderr_done) = (false, false);
+        while !(stdout_done && stderr_done) {
+            let (line, done) = tokio::select! {
+                line = stdout.next_line(), if !stdout_done => (line, &mut stdout_done),
+                line = stderr.next_line(), if !stderr_done => (line, &mut stderr_done),
+            };
+            match line {
+                // a dropped receiver only means nobody is listening anymore
+                Ok(Some(line)) => {
+                    if self.log_file.is_some() {
+                        transcript.push_str(&line);
+                        transcript.push('\n');
+                    }
+                    let _ = events.send(CompileEvent::LineEmitted(line)).await;
+                }
+                // the stream ended, or failed to read, which ends it too
+                _ => *done = true,
+            }
+        }
+        child.wait().await.map_err(|e| self.command_error(e))
+    }
+
+    /// Runs the compile command once per pass, with the custom steps in between
+    ///
+    /// The captured output of each pass is added to `transcript` when there's a `log_file`.
+    async fn run_passes(
+        &self,
+        events: Option<&Sender<CompileEvent>>,
+        transcript: &mut String,
+    ) -> Result<()> {
+        for pass in 1..=self.passes {
+            if self.log_file.is_some() {
+                transcript.push_str(&format!("--- pass {pass}\n"));
+            }
+            if let Some(tail) = self.log_tail {
+                tail.lock().await.start_pass().await;
+            }
+            let status = if let Some(events) = events {
+                let _ = events.send(CompileEvent::PassStarted(pass)).await;
+                self.stream(events, transcript).await?
+            } else {
+                match self.mode{
+                    CompilerMode::Spawn => {
+                        let status = self.spawn().await?;
+                        if self.log_file.is_some() {
+                            transcript.push_str("(the output isn't captured in `Spawn` mode)\n");
+                        }
+                        status
+                    }
+                    CompilerMode::Output => {
+                        let output = self.output().await?;
+                        if self.log_file.is_some() {
+                            transcript.push_str(&String::from_utf8_lossy(&output.stdout));
+                            transcript.push_str(&String::from_utf8_lossy(&output.stderr));
+                        }
+                        output.status
+                    }
+                }
+            };
+            if let Some(tail) = self.log_tail {
+                tail.lock().await.end_pass().await;
+            }
+            if !status.success() {
+                return Err(CompilerError::CompileFailed { pass, status });
+            }
+            for step in self.steps().iter().filter(|step| step.after_pass == pass) {
+                self.run_step(step).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the compile, retrying it with shell escape when `auto_shell_escape` asks for it
+    ///
+    /// With `auto_shell_escape`, if the log says the document needs shell escape, such as for
+    /// `minted`, the compile is retried once with shell escape, with a warning. It's off by
+    /// default, like `shell_escape`, since shell escape lets the document run any command.
+    /// Shell escape is enabled with the flag `distro` spells it with, `-shell-escape`, or
+    /// `-enable-write18` for MiKTeX.
+    pub(crate) async fn run(&self, events: Option<&Sender<CompileEvent>>) -> Result<CompileReport> {
+        let result = self.run_attempt(events).await;
+        if !self.auto_shell_escape || self.shell_escape || enables_shell_escape(self.flags) {
+            return result;
+        }
+        let Err(CompilerError::CompileFailed { .. }) = &result else {
+            return result;
+        };
+        match read_to_string(self.artifact_path("log")).await {
+            Ok(log) if needs_shell_escape(&log) => {}
+            _ => return result,
+        }
+        cprint!(
+            self,
+            Yellow,
+            "The project `{}` needs shell escape, retrying with `{}` enabled",
+            &self.proj_name,
+            self.distro.shell_escape_flag()
+        );
+        Job { shell_escape: true, ..*self }.run_attempt(events).await
+    }
+
+    async fn run_attempt(&self, events: Option<&Sender<CompileEvent>>) -> Result<CompileReport> {
+        match self.sandbox {
+            Some(_) => self.run_sandboxed(events).await,
+            None => self.run_once(events).await,
+        }
+    }
+
+    /// Compiles in a fresh temporary directory the sources are staged into, then copies the
+    /// files left in its `out_dir` back, even if the compile failed, so the log can be read
+    async fn run_sandboxed(&self, events: Option<&Sender<CompileEvent>>) -> Result<CompileReport> {
+        self.validate()?;
+        let work_dir = TempDir::new("", &self.proj_name);
+        // the one clone of a sandboxed compile, which is costly anyway
+        let mut config = self.config.clone();
+        config.out_dir = work_dir.join(DEFAULT_OUT_DIR);
+        config.work_dir = Some(work_dir.to_path_buf());
+        let compiler = Job { config: &config, ..*self };
+        create_dir_all(&work_dir).await?;
+        for path in self.dependencies().await? {
+            // sources outside the project stay out of the sandbox
+            if path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+                copy_all(&self.in_work_dir(&path), &work_dir.join(&path)).await?;
+            }
+        }
+        let result = compiler.run_once(events).await;
+        if metadata(&compiler.out_dir).await.is_ok() {
+            self.prepare_out_dir().await?;
+            copy_all(&compiler.out_dir, &self.out_dir).await?;
+        }
+        result
+    }
+
+    async fn run_once(&self, events: Option<&Sender<CompileEvent>>) -> Result<CompileReport> {
+        self.validate()?;
+        self.prepare_out_dir().await?;
+        let pretex = self.pretex();
+        if let Some(pretex) = &pretex {
+            let contents = format!("{pretex}\\input{{{}}}\n", &self.proj_name);
+            // unlike a `File`, finishes writing before the compiler reads it
+            write(self.in_work_dir(self.entry_file()), contents).await?;
+        }
+        for path in &self.copy_before_compile {
+            copy_all(path, &self.out_dir.join(path)).await?;
+        }
+        let mut transcript = String::new();
+        let passes = self.run_passes(events, &mut transcript).await;
+        self.append_log_file(&transcript, &passes).await;
+        // the entry file is removed even if a pass failed
+        if pretex.is_some() {
+            remove_file(self.in_work_dir(self.entry_file())).await?;
+        }
+        passes?;
+        let mut report = CompileReport {
+            passes: self.passes,
+            ..Default::default()
+        };
+        // the log has to be checked before cleaning removes it
+        let log = self.artifact_path("log");
+        if let Ok(log) = read_to_string(log).await {
+            report.warnings = Warnings::from_log(&log);
+            report.rerun_requested = needs_rerun(&log);
+            // with more passes, the report alone tells they weren't enough
+            if report.rerun_requested && self.passes == 1 {
+                cprint!(
+                    self,
+                    Yellow,
+                    "LaTeX requested a rerun for `{}`, increase `passes` to get the cross-references right",
+                    &self.proj_name
+                );
+            }
+            report.log_contents = Some(log);
+        }
+        if self.clean{
+            self.clean_artifacts().await?;
+        }
+        // if nothing failed then we have a successful compile
+        cprint!(self, Green, "The project `{}` successfully compiled!", &self.proj_name);
+        Ok(report)
+    }
+}
+
+/// A spawned compiler or step, killed when it's dropped before it exited, so a compile future
+/// that is dropped or whose task panics doesn't leave an orphaned compiler running
+///
+/// Since async drop isn't available, the kill is only started, with `start_kill`. Only the
+/// child itself is killed, so a `wrapper` should `exec` the compiler rather than run it as
+/// a child of its own. The child stays in the terminal's process group, so it still gets
+/// a Ctrl-C, and can read the terminal when an error stops it for input.
+pub(crate) struct ChildGuard(Child);
+
+impl ChildGuard {
+    /// Spawns `command`, returning the guarded child
+    pub(crate) fn spawn(command: &mut Command) -> std::io::Result<Self> {
+        command.spawn().map(Self)
+    }
+}
+
+impl Deref for ChildGuard {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.0
+    }
+}
+
+impl DerefMut for ChildGuard {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.0
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        // fails without sending anything once the child was waited on
+        let _ = self.0.start_kill();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use crate::tests::script;
+    use crate::{unique_temp_dir, Compiler, SandboxConfig};
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dropping_the_compile_kills_the_compiler() {
+        let dir = unique_temp_dir("test-", "drop");
+        std::fs::create_dir_all(&dir).unwrap();
+        let pid_file = dir.join("pid");
+        let compiler_path = script(&dir, "slowtex", &format!("echo $$ > {}\nexec sleep 30", pid_file.display()));
+        let mut compiler = Compiler::new("doc");
+        compiler.compiler = compiler_path;
+        compiler.quiet = true;
+        compiler.out_dir = dir.join("out");
+        let compile = compiler.compile();
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(500), compile).await.is_err());
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // a killed child is gone, or a zombie until it's reaped
+        let alive = || match std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+            Ok(stat) => stat.rsplit(')').next().is_some_and(|rest| !rest.trim_start().starts_with('Z')),
+            Err(_) => false,
+        };
+        for _ in 0..50 {
+            if !alive() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(!alive());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dropping_a_sandboxed_compile_removes_its_directory() {
+        let dir = unique_temp_dir("test-", "sandbox-drop");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("doc.tex"), "").unwrap();
+        let cwd_file = dir.join("cwd");
+        let compiler_path = script(&dir, "slowtex", &format!("pwd > {}\nexec sleep 30", cwd_file.display()));
+        let mut compiler = Compiler::new("doc");
+        compiler.compiler = compiler_path;
+        compiler.quiet = true;
+        compiler.out_dir = dir.join("out");
+        compiler.work_dir = Some(dir.clone());
+        compiler.sandbox = Some(SandboxConfig::default());
+        let compile = compiler.compile();
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(500), compile).await.is_err());
+        let sandbox_dir = std::fs::read_to_string(&cwd_file).unwrap();
+        assert!(!std::path::Path::new(sandbox_dir.trim()).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tectonic_keeps_the_log_and_intermediates() {
+        let compiler: Compiler = toml::from_str("proj_name = 'doc'\ncompiler = 'tectonic'").unwrap();
+        assert_eq!(
+            compiler.argv(),
+            ["tectonic", "-o", "out", "--keep-logs", "--keep-intermediates", "doc.tex"]
+        );
+    }
+
+    #[test]
+    fn tectonic_runs_a_single_pass() {
+        let compiler: Compiler = toml::from_str("proj_name = 'doc'\ncompiler = 'tectonic'\npasses = 3").unwrap();
+        let rule = compiler.to_makefile_rule().unwrap();
+        assert_eq!(rule.matches("\ttectonic ").count(), 1, "{rule}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tectonic_compile_cleans() {
+        let dir = unique_temp_dir("test-", "tectonic");
+        std::fs::create_dir_all(&dir).unwrap();
+        // fails without `--keep-intermediates`, since `tectonic` only keeps the `aux` file with it
+        let tectonic = script(
+            &dir,
+            "tectonic",
+            "case \" $* \" in *' --keep-intermediates '*) ;; *) exit 1;; esac\n\
+             while [ \"$1\" != -o ]; do shift; done\n\
+             touch \"$2/doc.pdf\" \"$2/doc.aux\" \"$2/doc.log\"",
+        );
+        let mut compiler = Compiler::new("doc");
+        compiler.compiler = tectonic;
+        compiler.quiet = true;
+        compiler.out_dir = dir.join("out");
+        compiler.compile().await.unwrap();
+        let left: Vec<_> = std::fs::read_dir(&compiler.out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["doc.pdf"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,23 +1,62 @@
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 use serde::{Deserialize, Serialize};
-use tokio::fs::{File, read_to_string, remove_file};
-use tokio::io::{AsyncWriteExt, Result};
+use tokio::fs::{
+    File, OpenOptions, canonicalize, copy, create_dir_all, metadata, read, read_dir, read_to_string, remove_dir_all,
+    remove_file, write,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::process::Command;
+use tokio::sync::mpsc::Sender;
 use toml::{from_str, to_string_pretty};
-use termcolor::Color::Green;
+use termcolor::Color::{Green, Red, Yellow};
 
 macro_rules! cprint {
-    ($color: expr, $($arg: tt)*) => ({
+    ($compiler: expr, $color: expr, $($arg: tt)*) => ({
         use std::io::Write;
         use termcolor::{ColorChoice, ColorSpec, StandardStream, WriteColor};
-        let mut stdout = StandardStream::stdout(ColorChoice::Auto);
-        let _ = stdout.set_color(ColorSpec::new().set_fg(Some($color)));
-        let _ = writeln!(&mut stdout, $($arg)*);
+        let compiler: &$crate::Compiler = &$compiler;
+        if !compiler.quiet {
+            let mut stream = match compiler.messages {
+                $crate::MessageStream::Stdout => StandardStream::stdout(ColorChoice::Auto),
+                $crate::MessageStream::Stderr => StandardStream::stderr(ColorChoice::Auto),
+            };
+            let _ = stream.set_color(ColorSpec::new().set_fg(Some($color)));
+            let _ = writeln!(&mut stream, $($arg)*);
+        }
     });
 }
 
+mod batch;
+mod engine;
+mod error;
+mod job;
+mod millis;
+mod report;
+mod watch;
+
+pub use batch::{compile_all_within, BatchOutcome, BatchReport};
+pub use engine::Engine;
+pub use error::{CompilerError, Result};
+pub use report::{
+    parse_log, CompileEvent, CompileReport, DiffResult, FontInfo, FontReport, LogEntry, LogEntryKind, RenderResult,
+    Warnings,
+};
+use job::ChildGuard;
+
 /// The Compiler configuration allows TexCreate to compile the project
+///
+/// A `Compiler` is `Send + Sync`, and the futures of its async methods are `Send`, so they can
+/// be spawned with `tokio::spawn` or awaited in the handlers of a multithreaded server.
+///
+/// The fields missing from a `compiler.toml` take their value from [`Compiler::default`].
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Compiler {
     // The LaTeX compiler to use, default: pdflatex
     compiler: String,
@@ -29,6 +68,798 @@ pub struct Compiler {
     clean: bool,
     // whether to spawn or output the job
     mode: CompilerMode,
+    // how many times to run the compiler, a single pass warns when its log asks for a rerun,
+    // default: 1
+    #[serde(default = "default_passes")]
+    passes: u32,
+    // the directory the compiler writes to, default: out
+    #[serde(default = "default_out_dir")]
+    out_dir: PathBuf,
+    // the extensions of the files removed from `out_dir` when cleaning, default: aux, log
+    #[serde(default = "default_clean_exts")]
+    clean_exts: Vec<String>,
+    // a command to run the compiler and the steps under, such as a container, default: none
+    #[serde(default)]
+    wrapper: Vec<String>,
+    // TeX code to run right after the document class is loaded, default: none
+    #[serde(default)]
+    preamble: Option<String>,
+    // whether to also set the interaction mode from the source, for wrappers that drop `-interaction`, default: false
+    #[serde(default)]
+    interaction_via_source: bool,
+    // the `(major, minor)` version of the PDF to produce, default: the engine's
+    #[serde(default)]
+    pdf_version: Option<(u8, u8)>,
+    // the comment written in DVI output instead of the date, such as a build identifier, default: none
+    #[serde(default)]
+    output_comment: Option<String>,
+    // the title, author, subject and keywords of the PDF, set through `hyperref`, default: none
+    #[serde(default)]
+    metadata: Option<PdfMetadata>,
+    // other files the document depends on, such as `\input` chapters or images, which are
+    // also the files staged into a sandbox, default: none
+    #[serde(default)]
+    includes: Vec<PathBuf>,
+    // whether to also follow the `\input` and `\include` directives of the sources, default: false
+    #[serde(default)]
+    scan_inputs: bool,
+    // files and directories copied into `out_dir` before the first pass, keeping their path
+    // relative to the project, so `assets/logo.png` goes to `out_dir/assets/logo.png`, default: none
+    #[serde(default)]
+    copy_before_compile: Vec<PathBuf>,
+    // whether cleaning also removes the copies of `copy_before_compile`, default: false
+    #[serde(default)]
+    clean_copied: bool,
+    // whether to skip writing the output, for quicker checks, default: false
+    #[serde(default)]
+    draft: bool,
+    // named variants of this configuration, compiled with `compile_profile`, default: none
+    #[serde(default)]
+    profiles: HashMap<String, ProfileOverride>,
+    // where the success, warning and error messages are written, default: Stdout
+    #[serde(default)]
+    messages: MessageStream,
+    // whether to skip writing any message, errors are still returned, default: false
+    #[serde(default)]
+    quiet: bool,
+    // commands run between passes, such as `makeglossaries`, default: none
+    #[serde(default)]
+    custom_steps: Vec<CustomStep>,
+    // whether to run `makeindex` after the first pass, for `\printindex`, default: false
+    #[serde(default)]
+    index: bool,
+    // whether to write SyncTeX data for editors, and in which format, default: Off
+    #[serde(default)]
+    synctex: SyncTex,
+    // a file the output of every compile is appended to, default: none
+    #[serde(default)]
+    log_file: Option<PathBuf>,
+    // whether to let the document run commands, such as for `minted`, default: false
+    #[serde(default)]
+    shell_escape: bool,
+    // whether to retry a failed compile with `-shell-escape` when the log asks for it, default: false
+    #[serde(default)]
+    auto_shell_escape: bool,
+    // the TeX distribution of the compiler, for the flags they spell differently, default: TexLive
+    #[serde(default)]
+    distro: Distribution,
+    // whether to compile in a restricted environment, for untrusted documents, default: none
+    #[serde(default)]
+    sandbox: Option<SandboxConfig>,
+    // how long the files must stay unchanged before `watch` recompiles, in milliseconds, default: 300
+    #[serde(default = "default_watch_debounce", with = "millis")]
+    watch_debounce: Duration,
+    // the glob patterns of the files `watch` ignores, on top of `out_dir`, default: none
+    #[serde(default)]
+    watch_ignore: Vec<String>,
+    // the directory the compiler runs in while sandboxed, the current directory otherwise
+    #[serde(skip)]
+    work_dir: Option<PathBuf>,
+}
+
+/// The comments `to_commented_string` writes above each field, in the order they're written
+const FIELD_DOCS: &[(&str, &str)] = &[
+    ("proj_name", "The project name, compiled from `proj_name.tex`"),
+    ("compiler", "The LaTeX compiler to use, such as pdflatex, xelatex or lualatex"),
+    ("flags", "Any extra flags to use when compiling"),
+    ("mode", "Whether to `Spawn` the compiler with its output shown, or capture its `Output`"),
+    ("passes", "How many times to run the compiler, raise it to resolve cross-references"),
+    ("out_dir", "The directory the compiler writes to"),
+    ("index", "Whether to run `makeindex` after the first pass, for `\\printindex`"),
+    ("custom_steps", "Commands run in `out_dir` after the pass `after_pass`, such as `makeglossaries`"),
+    ("clean", "Whether to remove the files with the `clean_exts` extensions after compiling"),
+    ("clean_exts", "The extensions of the files removed from `out_dir` when cleaning"),
+    ("includes", "Other files the document depends on, checked by `is_stale` and staged into the `sandbox`"),
+    ("scan_inputs", "Whether `is_stale` also follows the `\\input` and `\\include` directives of the sources"),
+    ("copy_before_compile", "Files and directories copied into `out_dir` before compiling"),
+    ("clean_copied", "Whether cleaning also removes the copies of `copy_before_compile`"),
+    ("synctex", "Whether to write SyncTeX data for editors, `Off`, `Compressed` or `Uncompressed`"),
+    ("draft", "Whether to skip writing the output, for quicker checks"),
+    ("log_file", "A file the output of every compile is appended to, separate from TeX's own log"),
+    ("messages", "Where messages are written, `Stdout` or `Stderr`"),
+    ("quiet", "Whether to skip writing any message, errors are still returned"),
+    ("shell_escape", "Whether to let the document run commands, such as for `minted`, a security risk"),
+    ("auto_shell_escape", "Whether to retry a failed compile with `-shell-escape` when the log asks for it"),
+    ("distro", "The TeX distribution of the compiler, `TexLive` or `Miktex`, which spell some flags differently"),
+    ("sandbox", "Whether to compile in a restricted environment, with a `wrapper` command and the `env` kept"),
+    ("watch_debounce", "How long the files must stay unchanged before `watch` recompiles, in milliseconds"),
+    ("watch_ignore", "The glob patterns of the files `watch` ignores, such as `*.swp`, on top of `out_dir`"),
+    ("wrapper", "A command to run the compiler and the steps under, such as a container"),
+    ("interaction_via_source", "Whether to also set the `-interaction` mode in the source, for wrappers dropping it"),
+    ("preamble", "TeX code to run right after the document class is loaded"),
+    ("pdf_version", "The [major, minor] version of the PDF to produce"),
+    ("output_comment", "The comment written in DVI output instead of the date, such as a git SHA, ignored for PDFs"),
+    ("metadata", "The title, author, subject and keywords of the PDF, the document must load `hyperref`"),
+    ("profiles", "Named variants of this configuration, overriding flags, passes, draft or clean"),
+];
+
+/// The flags `Compiler::new` starts with on this platform
+///
+/// - `-interaction=nonstopmode` everywhere, so an error stops the compiler instead of
+///   waiting for input, which would hang when the output is captured
+/// - `-file-line-error` except on Windows, for `file:line:` error messages. TeX Live accepts it,
+///   but MiKTeX spells it `-c-style-errors`
+///
+/// The installed distribution isn't detected: Windows only stands in for MiKTeX, since it's the
+/// usual distribution there. TeX Live on Windows goes without `-file-line-error` and MiKTeX
+/// elsewhere gets it anyway, so use [`Compiler::with_flags`] to start from the right flags.
+pub fn platform_default_flags() -> Vec<String> {
+    let mut flags = vec!["-interaction=nonstopmode".to_string()];
+    if !cfg!(windows) {
+        flags.push("-file-line-error".to_string());
+    }
+    flags
+}
+
+/// The flags the crate sets itself, with the field that configures each one
+///
+/// The flags configured by a field conflict with it, so `validate` rejects them in `flags`.
+/// The others have no field, but the crate relies on them: the [`platform_default_flags`]
+/// set `-interaction`, and `shell_escape` and `auto_shell_escape` add `-shell-escape`, which
+/// is also accepted in `flags`.
+const MANAGED_FLAGS: &[(&str, Option<&str>)] = &[
+    ("-output-directory", Some("out_dir")),
+    ("-jobname", Some("proj_name")),
+    ("-synctex", Some("synctex")),
+    ("-output-comment", Some("output_comment")),
+    ("-draftmode", Some("draft")),
+    ("-no-pdf", Some("draft")),
+    ("-interaction", None),
+    ("-shell-escape", None),
+    ("-no-shell-escape", None),
+    ("-shell-restricted", None),
+    ("-enable-write18", None),
+    ("-disable-write18", None),
+];
+
+/// The name of a flag, without its leading dashes or its `=value` or ` value`
+fn flag_name(flag: &str) -> &str {
+    let flag = flag.trim_start_matches('-');
+    flag.split(['=', ' ']).next().unwrap_or(flag)
+}
+
+/// The managed flag matching `flag`, if any
+fn managed_flag(flag: &str) -> Option<&'static (&'static str, Option<&'static str>)> {
+    let name = flag_name(flag);
+    MANAGED_FLAGS.iter().find(|(managed, _)| flag_name(managed) == name)
+}
+
+/// Whether the crate controls `flag` itself, such as `-output-directory` or `-synctex`, so a
+/// config editor can grey it out
+///
+/// Both `-flag` and `--flag` are recognized, with or without a `=value`, or a ` value` when
+/// the flag and its value are given as one string.
+pub fn is_managed_flag(flag: &str) -> bool {
+    managed_flag(flag).is_some()
+}
+
+fn default_passes() -> u32 {
+    1
+}
+
+/// The directory the compiler writes to when `out_dir` isn't set
+pub const DEFAULT_OUT_DIR: &str = "out";
+
+/// The extensions of the files TeX and its tools write, the only ones `prune_artifacts` removes
+///
+/// The extensions made of several parts come first, so `report.synctex.gz` is matched as a
+/// `synctex.gz` file of `report` rather than a `gz` file of `report.synctex`.
+const ARTIFACT_EXTS: &[&str] = &[
+    "synctex.gz", "run.xml", "pdf", "dvi", "xdv", "aux", "log", "toc", "lof", "lot", "out", "synctex",
+    "idx", "ind", "ilg", "bbl", "blg", "bcf", "fls", "fdb_latexmk", "nav", "snm", "vrb", "glo", "gls", "glg",
+    "ist", "acn", "acr", "alg",
+];
+
+fn default_out_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_OUT_DIR)
+}
+
+fn default_watch_debounce() -> Duration {
+    Duration::from_millis(300)
+}
+
+fn default_clean_exts() -> Vec<String> {
+    vec!["aux".to_string(), "log".to_string()]
+}
+
+/// The modification time of a source file
+async fn modified(path: &Path) -> Result<SystemTime> {
+    Ok(source_metadata(path).await?.modified()?)
+}
+
+/// The size of a source file in bytes
+async fn source_len(path: &Path) -> Result<u64> {
+    Ok(source_metadata(path).await?.len())
+}
+
+async fn source_metadata(path: &Path) -> Result<std::fs::Metadata> {
+    match metadata(path).await {
+        Ok(meta) => Ok(meta),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(CompilerError::SourceNotFound(path.to_path_buf())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A path for a new temporary directory, unique to this process and call, for `purpose`
+fn unique_temp_dir(purpose: &str, proj_name: &str) -> PathBuf {
+    static TEMP_DIRS: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir().join(format!(
+        "texc-{purpose}{proj_name}-{}-{}",
+        std::process::id(),
+        TEMP_DIRS.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// A temporary directory that's removed when dropped, so it's gone even if the future using it
+/// is dropped mid-compile
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(purpose: &str, proj_name: &str) -> Self {
+        Self(unique_temp_dir(purpose, proj_name))
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Copies a file, or a directory with everything in it, creating the missing parents of `to`
+async fn copy_all(from: &Path, to: &Path) -> Result<()> {
+    let mut pending = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = pending.pop() {
+        if source_metadata(&from).await?.is_dir() {
+            create_dir_all(&to).await?;
+            let mut entries = read_dir(&from).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                pending.push((entry.path(), to.join(entry.file_name())));
+            }
+        } else {
+            if let Some(parent) = to.parent() {
+                create_dir_all(parent).await?;
+            }
+            copy(&from, &to).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Follows a log file as the compiler writes it, for `compile_tailing_log`
+///
+/// Each pass rewrites the log from the start, so the compile calls [`LogTail::end_pass`] once
+/// a pass is done and [`LogTail::start_pass`] before the next one starts.
+struct LogTail {
+    path: PathBuf,
+    lines: Sender<String>,
+    // the modification time of a log that was already sent, or a previous compile's, which
+    // isn't followed until it's rewritten
+    stale: Option<SystemTime>,
+    // how much of the log has been read
+    offset: u64,
+    // the start of a line that isn't written entirely yet
+    partial: Vec<u8>,
+}
+
+impl LogTail {
+    async fn new(path: PathBuf, lines: Sender<String>) -> Self {
+        let stale = modified(&path).await.ok();
+        Self {
+            path,
+            lines,
+            stale,
+            offset: 0,
+            partial: vec![],
+        }
+    }
+
+    /// Starts following the log the next pass writes, skipping the current one
+    async fn start_pass(&mut self) {
+        self.stale = modified(&self.path).await.ok();
+        self.offset = 0;
+        self.partial.clear();
+    }
+
+    /// Sends the rest of the log of a pass that is done
+    async fn end_pass(&mut self) {
+        self.forward().await;
+        self.finish().await;
+    }
+
+    /// Sends the lines written since the last call
+    async fn forward(&mut self) {
+        let Ok(metadata) = metadata(&self.path).await else {
+            return;
+        };
+        if self.stale.is_some() && metadata.modified().ok() == self.stale {
+            return;
+        }
+        self.stale = None;
+        let Ok(mut file) = File::open(&self.path).await else {
+            return;
+        };
+        let mut new = vec![];
+        if file.seek(SeekFrom::Start(self.offset)).await.is_err() || file.read_to_end(&mut new).await.is_err() {
+            return;
+        }
+        self.offset += new.len() as u64;
+        self.partial.extend(new);
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+            let _ = self.lines.send(line).await;
+        }
+    }
+
+    /// Sends the last line, if the log doesn't end with a newline
+    async fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            let _ = self.lines.send(String::from_utf8_lossy(&self.partial).to_string()).await;
+            self.partial.clear();
+        }
+    }
+}
+
+/// The absolute path of the executable `program`, which is looked up directly if it names
+/// a path, or searched in `PATH`, trying the extensions of `PATHEXT` on Windows
+async fn find_executable(program: &str) -> Option<PathBuf> {
+    let program = Path::new(program);
+    let candidates: Vec<PathBuf> = if program.components().count() > 1 {
+        vec![program.to_path_buf()]
+    } else {
+        let dirs = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&dirs).map(|dir| dir.join(program)).collect()
+    };
+    let exts: Vec<String> = match std::env::var("PATHEXT") {
+        Ok(exts) if cfg!(windows) && program.extension().is_none() => {
+            exts.split(';').filter(|ext| !ext.is_empty()).map(str::to_string).collect()
+        }
+        _ => vec![String::new()],
+    };
+    for candidate in candidates {
+        for ext in &exts {
+            let mut path = candidate.clone().into_os_string();
+            path.push(ext);
+            let path = PathBuf::from(path);
+            if is_executable(&path).await {
+                return std::path::absolute(&path).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Whether `path` is a file that can be run
+async fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = metadata(path).await else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    metadata.is_file()
+}
+
+/// Runs an external tool, returning its stdout
+///
+/// Fails with [`CompilerError::ToolNotFound`] if it isn't installed, and with
+/// [`CompilerError::StepFailed`] if it exits unsuccessfully.
+async fn run_tool(tool: &str, args: &[&OsStr]) -> Result<String> {
+    let output = Command::new(tool).args(args).output().await.map_err(|e| match e.kind() {
+        ErrorKind::NotFound => CompilerError::ToolNotFound(tool.to_string()),
+        _ => e.into(),
+    })?;
+    if !output.status.success() {
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+        return Err(CompilerError::StepFailed {
+            command: format!("{tool} {}", args.join(" ")),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The page count of a PDF, from the `Pages:` line of `pdfinfo`
+async fn pdf_pages(path: &Path) -> Result<u32> {
+    let info = run_tool("pdfinfo", &[path.as_os_str()]).await?;
+    info.lines()
+        .find_map(|line| line.strip_prefix("Pages:"))
+        .and_then(|pages| pages.trim().parse().ok())
+        .ok_or_else(|| {
+            let message = format!("`pdfinfo` didn't count the pages of `{}`", path.display());
+            std::io::Error::new(ErrorKind::InvalidData, message).into()
+        })
+}
+
+/// Writes files into a new zip archive, named after the last component of their path
+#[cfg(feature = "zip")]
+fn write_zip(zip_path: &Path, entries: Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(zip_path)?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (path, contents) in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        zip.start_file(name, options).map_err(std::io::Error::other)?;
+        zip.write_all(&contents)?;
+    }
+    zip.finish().map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+/// Quotes a word for a POSIX shell, and escapes it for a Makefile recipe
+fn recipe_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=./:,+@%".contains(c));
+    let quoted = if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    };
+    quoted.replace('$', "$$")
+}
+
+/// Quotes and joins words into a Makefile recipe line
+fn recipe_words<'a>(words: impl IntoIterator<Item = &'a str>) -> String {
+    words.into_iter().map(recipe_word).collect::<Vec<_>>().join(" ")
+}
+
+/// Formats a time as an ISO 8601 UTC timestamp, such as `2023-03-20T14:05:09Z`
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // converts days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// The files named by the `\input{...}` and `\include{...}` directives of a TeX source,
+/// ignoring comments
+fn tex_inputs(source: &str) -> Vec<&str> {
+    let mut inputs = vec![];
+    for line in source.lines() {
+        // `%` starts a comment, unless it's escaped as `\%`
+        let code = line
+            .char_indices()
+            .find(|&(i, c)| c == '%' && !line[..i].ends_with('\\'))
+            .map_or(line, |(i, _)| &line[..i]);
+        for directive in ["\\input{", "\\include{"] {
+            let mut rest = code;
+            while let Some(start) = rest.find(directive) {
+                rest = &rest[start + directive.len()..];
+                let Some(end) = rest.find('}') else {
+                    break;
+                };
+                let name = rest[..end].trim();
+                if !name.is_empty() {
+                    inputs.push(name);
+                }
+                rest = &rest[end..];
+            }
+        }
+    }
+    inputs
+}
+
+/// The name of a source without its extension, such as `doc` for `doc.tex` or `./doc.tex`,
+/// if it's in the current directory, as `proj_name` requires
+fn current_dir_stem(source: &str) -> Option<&str> {
+    let source = Path::new(source);
+    let parent = source.parent()?;
+    if !parent.as_os_str().is_empty() && parent != Path::new(".") {
+        return None;
+    }
+    source.file_stem()?.to_str()
+}
+
+/// How many lines at the top of a source are searched for `% !TEX` magic comments, as TeXShop does
+const MAGIC_COMMENT_LINES: usize = 20;
+
+/// The `% !TEX key = value` magic comments at the top of a TeX source, with the keys lowercased
+///
+/// `%!TEX` without the space, and the `TS-program` spelling of TeXShop, are recognized too.
+fn magic_comments(source: &str) -> Vec<(String, &str)> {
+    source
+        .lines()
+        .take(MAGIC_COMMENT_LINES)
+        .filter_map(|line| {
+            let comment = line.trim_start().strip_prefix('%')?.trim_start();
+            let directive = comment.get(..4).filter(|tex| tex.eq_ignore_ascii_case("!TEX"))?;
+            let (key, value) = comment[directive.len()..].split_once('=')?;
+            let key = key.trim().to_ascii_lowercase();
+            let key = if key == "ts-program" { "program".to_string() } else { key };
+            Some((key, value.trim()))
+        })
+        .collect()
+}
+
+/// The messages of the packages that need shell escape, lowercased, such as `minted`'s
+/// `Package minted Error: You must invoke LaTeX with the -shell-escape flag.`
+const SHELL_ESCAPE_REQUESTS: &[&str] = &[
+    "you must invoke latex with the -shell-escape flag",
+    "you must invoke latex with the --shell-escape flag",
+    // `svg`
+    "you didn't enable `shell escape'",
+    // `gnuplottex`
+    "shell escape not enabled",
+    // `epstopdf`
+    "shell escape feature is not enabled",
+];
+
+/// Whether a LaTeX log says the document needs shell escape, as `minted` or `svg` do
+///
+/// Only the messages of [`SHELL_ESCAPE_REQUESTS`] count, since retrying enables shell escape,
+/// and other lines mentioning it, such as the banner of `shellesc` or the source echoed in
+/// the context of an unrelated error, don't ask for it. `\write18` isn't looked for either,
+/// since every log starts by saying whether it's enabled.
+fn needs_shell_escape(log: &str) -> bool {
+    log.lines().any(|line| {
+        let line = line.to_lowercase();
+        SHELL_ESCAPE_REQUESTS.iter().any(|request| line.contains(request))
+    })
+}
+
+/// Whether the flags already enable shell escape, in either distribution's spelling
+fn enables_shell_escape(flags: &[String]) -> bool {
+    flags.iter().any(|flag| {
+        matches!(flag.trim_start_matches('-'), "shell-escape" | "enable-write18")
+    })
+}
+
+/// Whether a LaTeX log asks for another run to settle cross-references
+///
+/// Only the requests themselves count, not lines that merely mention reruns, such as the
+/// banner of `rerunfilecheck`, which `hyperref` loads, `Rerun checks for auxiliary files`.
+fn needs_rerun(log: &str) -> bool {
+    log.lines().any(|line| {
+        line.contains("Rerun to get")
+            || line.contains("Label(s) may have changed")
+            // the continuation lines of its warnings are padded to align with the first
+            || (line.starts_with("(rerunfilecheck)") && line.contains("Rerun"))
+            || line.contains("Please rerun")
+    })
+}
+
+/// Settings overriding those of a configuration for a single compile, borrowed rather than
+/// cloned, for `compile_with`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompileOverrides<'a> {
+    /// Replaces `flags`
+    pub flags: Option<&'a [String]>,
+    /// Replaces `passes`
+    pub passes: Option<u32>,
+    /// Replaces `draft`
+    pub draft: Option<bool>,
+    /// Replaces `clean`
+    pub clean: Option<bool>,
+}
+
+/// The settings a profile overrides, with the rest taken from the base configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ProfileOverride {
+    /// Replaces `flags`
+    pub flags: Option<Vec<String>>,
+    /// Replaces `passes`
+    pub passes: Option<u32>,
+    /// Replaces `draft`
+    pub draft: Option<bool>,
+    /// Replaces `clean`
+    pub clean: Option<bool>,
+}
+
+/// A command run between passes of the compiler, such as `makeglossaries` or `makeindex`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CustomStep {
+    /// The command, split on whitespace into the program and its arguments
+    pub command: String,
+    /// The pass after which the command runs, counting from 1
+    pub after_pass: u32,
+}
+
+/// The document information of the PDF, set from the configuration rather than the source
+///
+/// It's set with `hyperref`'s `\hypersetup` at the beginning of the document, so the
+/// document has to load `hyperref`, or the compile fails with an undefined `\hypersetup`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PdfMetadata {
+    /// The `Title` entry, default: none
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The `Author` entry, default: none
+    #[serde(default)]
+    pub author: Option<String>,
+    /// The `Subject` entry, default: none
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// The `Keywords` entry, joined with commas, default: none
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+impl PdfMetadata {
+    /// The `\hypersetup` keys of the entries that are set
+    fn hypersetup_keys(&self) -> Vec<String> {
+        let keywords = (!self.keywords.is_empty()).then(|| self.keywords.join(", "));
+        [
+            ("pdftitle", self.title.as_ref()),
+            ("pdfauthor", self.author.as_ref()),
+            ("pdfsubject", self.subject.as_ref()),
+            ("pdfkeywords", keywords.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| format!("{key}={{{}}}", tex_escape(value))))
+        .collect()
+    }
+}
+
+/// Escapes the characters TeX treats specially, so `s` is typeset as written
+fn tex_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '^' | '~' => escaped.push_str(&format!("\\{c}{{}}")),
+            '{' | '}' | '%' | '#' | '&' | '$' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A restricted environment to compile untrusted documents in, such as for a public
+/// rendering service
+///
+/// The sources are staged into a fresh temporary directory, the compiler and the steps run
+/// there with only the variables of `env`, and the files left in its `out_dir` are copied
+/// back. This is best-effort: it keeps the compile away from the project, the service's
+/// secrets in its environment and its working directory, but the compiler still runs as the
+/// same user, so it can read anything that user can unless `wrapper` isolates it further,
+/// such as `["unshare", "--user", "--net"]` on Linux to also cut the network, or a `bwrap`
+/// command. Keep shell escape disabled for untrusted documents, a sandbox doesn't replace it.
+///
+/// Only the [`Compiler::dependencies`] are staged: the source, the `includes`, and with
+/// `scan_inputs`, the files they `\input` or `\include`. Any other file the document reads,
+/// such as an `\includegraphics` image, is missing in the sandbox unless it's listed in
+/// `includes`, and the compile fails.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SandboxConfig {
+    /// A command to run the compiler and the steps under, default: none
+    #[serde(default)]
+    pub wrapper: Vec<String>,
+    /// The environment variables kept, every other one is removed, default: none
+    ///
+    /// Without `PATH`, the programs are searched in the system's default directories, such
+    /// as `/bin` and `/usr/bin`, so keep it for a TeX distribution installed elsewhere.
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+impl SandboxConfig {
+    /// Clears the environment of `command` down to the variables of `env`
+    fn restrict(&self, command: &mut Command) {
+        command.env_clear();
+        for key in &self.env {
+            if let Some(value) = std::env::var_os(key) {
+                command.env(key, value);
+            }
+        }
+    }
+}
+
+/// Whether the compiler writes SyncTeX data, which lets editors jump between source and output
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SyncTex {
+    /// No SyncTeX data, which is also the engines' default, so no flag is passed
+    #[default]
+    Off,
+    /// `proj_name.synctex.gz`, with `-synctex=1`
+    Compressed,
+    /// `proj_name.synctex`, with `-synctex=-1`, for editors that can't read the compressed file
+    Uncompressed,
+}
+
+impl SyncTex {
+    /// The flag that selects this format, if any
+    pub fn flag(&self) -> Option<&'static str> {
+        match self {
+            Self::Off => None,
+            Self::Compressed => Some("-synctex=1"),
+            Self::Uncompressed => Some("-synctex=-1"),
+        }
+    }
+    /// The extension of the file this format produces, if any
+    pub fn ext(&self) -> Option<&'static str> {
+        match self {
+            Self::Off => None,
+            Self::Compressed => Some("synctex.gz"),
+            Self::Uncompressed => Some("synctex"),
+        }
+    }
+}
+
+/// The TeX distribution a compiler comes from, for the flags they spell differently
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Distribution {
+    /// TeX Live, and MacTeX, which is built from it
+    #[default]
+    TexLive,
+    /// MiKTeX, the usual distribution on Windows
+    Miktex,
+}
+
+impl Distribution {
+    /// The flag that enables shell escape, `-shell-escape`, or `-enable-write18` for MiKTeX
+    pub fn shell_escape_flag(&self) -> &'static str {
+        match self {
+            Self::TexLive => "-shell-escape",
+            Self::Miktex => "-enable-write18",
+        }
+    }
+}
+
+/// The stream the crate writes its colored messages to
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MessageStream {
+    #[default]
+    Stdout,
+    /// Keeps stdout free for machine-readable output
+    Stderr,
 }
 
 #[derive(Debug, Copy, Clone, Deserialize, Serialize)]
@@ -39,58 +870,565 @@ pub enum CompilerMode{
 
 
 
+impl Default for Compiler {
+    /// The configuration of [`Compiler::new`], with an empty `proj_name` that has to be set
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
 impl Compiler {
     /// Create a new compiler configuration given a project name, and has default compiler, `pdflatex`
+    ///
+    /// The flags start as the [`platform_default_flags`].
     pub fn new(proj_name: &str) -> Self {
         Self {
             compiler: "pdflatex".to_string(),
             proj_name: proj_name.to_string(),
-            flags: vec![],
+            flags: platform_default_flags(),
             clean: true,
             mode: CompilerMode::Output,
+            passes: default_passes(),
+            out_dir: default_out_dir(),
+            clean_exts: default_clean_exts(),
+            wrapper: vec![],
+            preamble: None,
+            interaction_via_source: false,
+            pdf_version: None,
+            output_comment: None,
+            metadata: None,
+            includes: vec![],
+            scan_inputs: false,
+            copy_before_compile: vec![],
+            clean_copied: false,
+            draft: false,
+            profiles: HashMap::new(),
+            messages: MessageStream::Stdout,
+            quiet: false,
+            custom_steps: vec![],
+            index: false,
+            synctex: SyncTex::Off,
+            log_file: None,
+            shell_escape: false,
+            auto_shell_escape: false,
+            distro: Distribution::TexLive,
+            sandbox: None,
+            watch_debounce: default_watch_debounce(),
+            watch_ignore: vec![],
+            work_dir: None,
+        }
+    }
+    /// Creates a new compiler configuration like [`Compiler::new`], with the first installed
+    /// engine found by [`Engine::detect`] rather than `pdflatex`
+    ///
+    /// Fails with [`CompilerError::CompilerNotFound`] if none of them is installed.
+    pub async fn new_auto(proj_name: &str) -> Result<Self> {
+        let engine = Engine::detect().await.ok_or_else(|| {
+            let names: Vec<&str> = Engine::DETECT_ORDER.iter().map(Engine::name).collect();
+            CompilerError::CompilerNotFound(names.join(", "))
+        })?;
+        let mut compiler = Self::new(proj_name);
+        compiler.compiler = engine.name().to_string();
+        Ok(compiler)
+    }
+    /// Replaces the flags, such as to start from none instead of the platform defaults
+    pub fn with_flags(mut self, flags: Vec<String>) -> Self {
+        self.flags = flags;
+        self
+    }
+    /// Sets where the success, warning and error messages are written
+    pub fn with_messages(mut self, messages: MessageStream) -> Self {
+        self.messages = messages;
+        self
+    }
+    /// Creates a `Compiler` from an existing compile command line, such as
+    /// `pdflatex -shell-escape report.tex` from a build script
+    ///
+    /// The first element is the compiler and the last one ending in `.tex` is the source,
+    /// which must be in the current directory. The flags the crate manages itself,
+    /// `-output-directory`, `-synctex`, `-output-comment`, the engine's draft flag and
+    /// `-shell-escape` or `-enable-write18`, set `out_dir`, `synctex`, `output_comment`,
+    /// `draft` and `shell_escape`, with `distro` set to MiKTeX for `-enable-write18`. Every
+    /// other flag is kept in `flags`, and the other fields have their [`Compiler::new`] defaults.
+    ///
+    /// `proj_name` names both the source and the output, so a `-jobname` naming the output
+    /// differently fails with [`CompilerError::InvalidConfig`], as do the other managed flags
+    /// that can't be converted, such as `-draftmode` for `xelatex`. The configuration is then
+    /// checked with [`Compiler::validate`], so it's always valid.
+    pub fn from_argv(argv: &[String]) -> Result<Self> {
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| CompilerError::InvalidConfig("the command line is empty".to_string()))?;
+        let source = args
+            .iter()
+            .rposition(|arg| arg.ends_with(".tex"))
+            .ok_or_else(|| CompilerError::InvalidConfig("the command line has no `.tex` source".to_string()))?;
+        let proj_name = current_dir_stem(&args[source]).ok_or_else(|| {
+            CompilerError::InvalidConfig(format!("the source `{}` must be in the current directory", &args[source]))
+        })?;
+        let mut compiler = Self::new(proj_name).with_flags(vec![]);
+        compiler.compiler = program.clone();
+        let draft_flag = compiler.engine().draft_flag();
+        let mut args = args.iter().enumerate().filter(|(i, _)| *i != source).map(|(_, arg)| arg);
+        while let Some(arg) = args.next() {
+            // TeX accepts both `-flag` and `--flag`
+            let flag = format!("-{}", arg.trim_start_matches('-'));
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag.as_str(), None),
+            };
+            match name {
+                "-output-directory" => {
+                    let value = value.or_else(|| args.next().cloned()).ok_or_else(|| {
+                        CompilerError::InvalidConfig("`-output-directory` has no directory".to_string())
+                    })?;
+                    compiler.out_dir = PathBuf::from(value);
+                }
+                "-synctex" => {
+                    let value = value.or_else(|| args.next().cloned()).unwrap_or_default();
+                    compiler.synctex = match value.parse::<i32>() {
+                        Ok(0) => SyncTex::Off,
+                        Ok(n) if n < 0 => SyncTex::Uncompressed,
+                        Ok(_) => SyncTex::Compressed,
+                        Err(_) => {
+                            return Err(CompilerError::InvalidConfig(format!(
+                                "`-synctex` expects a number, not `{value}`"
+                            )))
+                        }
+                    };
+                }
+                _ if value.is_none() && draft_flag == Some(name) => compiler.draft = true,
+                "-shell-escape" if value.is_none() => compiler.shell_escape = true,
+                // keeps the spelling of the command line
+                "-enable-write18" if value.is_none() => {
+                    compiler.shell_escape = true;
+                    compiler.distro = Distribution::Miktex;
+                }
+                "-output-comment" => {
+                    let value = value.or_else(|| args.next().cloned()).ok_or_else(|| {
+                        CompilerError::InvalidConfig("`-output-comment` has no comment".to_string())
+                    })?;
+                    compiler.output_comment = Some(value);
+                }
+                "-jobname" => {
+                    let value = value.or_else(|| args.next().cloned()).unwrap_or_default();
+                    if value != compiler.proj_name {
+                        return Err(CompilerError::InvalidConfig(format!(
+                            "`-jobname={value}` differs from the source's name, which `proj_name` gives the output"
+                        )));
+                    }
+                }
+                _ => match managed_flag(arg) {
+                    Some((_, Some(field))) => {
+                        return Err(CompilerError::InvalidConfig(format!(
+                            "`{arg}` can't be converted to `{field}` for `{program}`"
+                        )))
+                    }
+                    _ => compiler.flags.push(arg.clone()),
+                },
+            }
+        }
+        compiler.validate()?;
+        Ok(compiler)
+    }
+    /// Applies the `% !TEX` magic comments of the source, the convention of editors such as
+    /// TeXShop and VS Code, so the document compiles the same in them and with TexCreate
+    ///
+    /// The first lines of `proj_name.tex` are searched for:
+    /// - `% !TEX program = xelatex`, which sets `compiler`
+    /// - `% !TEX root = main.tex`, which sets `proj_name`, so `main.tex` is compiled instead.
+    ///   Like the source, the root must be in the current directory
+    /// - `% !TEX options = -shell-escape`, whose flags are appended to `flags`
+    ///
+    /// Other directives, such as `% !TEX encoding`, are ignored. The magic comments of the
+    /// root itself aren't read.
+    pub async fn apply_magic_comments(&mut self) -> Result<()> {
+        let path = self.source_path();
+        let source = match read_to_string(&path).await {
+            Ok(source) => source,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(CompilerError::SourceNotFound(path)),
+            Err(e) => return Err(e.into()),
+        };
+        for (key, value) in magic_comments(&source) {
+            match key.as_str() {
+                "program" if !value.is_empty() => self.compiler = value.to_string(),
+                "root" => match current_dir_stem(value) {
+                    Some(stem) => self.proj_name = stem.to_string(),
+                    None => {
+                        return Err(CompilerError::InvalidConfig(format!(
+                            "the `% !TEX root` `{value}` must be in the current directory"
+                        )))
+                    }
+                },
+                "options" => self.flags.extend(value.split_whitespace().map(str::to_string)),
+                _ => {}
+            }
         }
+        Ok(())
     }
     /// Creates a `Compiler` by reading `compiler.toml`
     pub async fn from_file() -> Result<Self> {
         let s = read_to_string("compiler.toml").await?;
-        Ok(from_str(&s).unwrap())
+        Ok(from_str(&s)?)
     }
     /// Turns `Compiler` into a TOML string
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
         to_string_pretty(&self).unwrap()
     }
-    /// Creates a new `compiler.toml` file.
+    /// Turns `Compiler` into a TOML string of only `proj_name` and the fields that differ from
+    /// their default, for terse, diff-friendly configuration files
+    ///
+    /// The missing fields take their default back when the file is read. The default flags
+    /// are the [`platform_default_flags`], so a file written without `flags` gets the defaults
+    /// of the platform it's read on.
+    pub fn to_minimal_string(&self) -> String {
+        let table = |compiler: &Self| match toml::Value::try_from(compiler).unwrap() {
+            toml::Value::Table(table) => table,
+            _ => unreachable!("`Compiler` serializes to a table"),
+        };
+        let defaults = table(&Self::new(&self.proj_name));
+        let mut minimal = table(self);
+        minimal.retain(|key, value| key != "proj_name" && defaults.get(key) != Some(value));
+        // the project name leads, as the one field every configuration sets
+        let proj_name = toml::Value::String(self.proj_name.clone());
+        format!("proj_name = {proj_name}\n{}", to_string_pretty(&minimal).unwrap())
+    }
+    /// Turns `Compiler` into a TOML string with a comment describing each field
+    ///
+    /// Unlike `to_string`, the fields are grouped by purpose rather than declaration order,
+    /// which makes generated `compiler.toml` files easier to read for new users.
+    pub fn to_commented_string(&self) -> String {
+        let table = match toml::Value::try_from(self).unwrap() {
+            toml::Value::Table(table) => table,
+            _ => unreachable!("`Compiler` serializes to a table"),
+        };
+        let mut s = String::from(
+            "# The TexCreate compiler configuration for this project\n\
+             # See https://texcreate.mkproj.com for more information\n",
+        );
+        for (key, doc) in FIELD_DOCS {
+            if let Some(value) = table.get(*key) {
+                s.push_str(&format!("\n# {doc}\n{key} = {value}\n"));
+            }
+        }
+        // fields without a comment still have to round-trip
+        for (key, value) in &table {
+            if !FIELD_DOCS.iter().any(|(field, _)| field == key) {
+                s.push_str(&format!("\n{key} = {value}\n"));
+            }
+        }
+        s
+    }
+    /// Creates a new `compiler.toml` file, with comments describing each field.
     ///
     /// Since `Compiler` contains the field, `proj_name`, the file will be created
     /// in the correct path.
     pub async fn create_file(&self) -> Result<()> {
-        let s = self.to_string();
+        let s = self.to_commented_string();
         let path = PathBuf::from(&self.proj_name).join("compiler.toml");
         let mut file = File::create(path).await?;
         file.write_all(s.as_bytes()).await?;
         Ok(())
     }
+    /// Adds the build artifacts to the project's `.gitignore`.
+    ///
+    /// The entries are derived from `out_dir` and the extensions removed when cleaning, and are
+    /// appended to an existing `.gitignore`, skipping the ones it already lists. `out_dir` is
+    /// anchored to the project, such as `/build/` for `./build`, and left out with a warning
+    /// when it's outside the project, since a `.gitignore` can't name it then.
+    pub async fn create_gitignore(&self) -> Result<()> {
+        let path = PathBuf::from(&self.proj_name).join(".gitignore");
+        let existing = match read_to_string(&path).await {
+            Ok(s) => s,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = vec![];
+        match self.out_dir_in_project() {
+            Some(out_dir) => entries.push(format!("/{out_dir}/")),
+            None => cprint!(
+                self,
+                Yellow,
+                "`out_dir` `{}` is outside the project, so it isn't added to `.gitignore`",
+                self.out_dir.display()
+            ),
+        }
+        entries.extend(self.clean_exts().iter().map(|ext| format!("*.{ext}")));
+        entries.retain(|entry| {
+            // an unanchored directory, such as `out/`, ignores it too
+            let unanchored = entry.strip_prefix('/').unwrap_or(entry);
+            !existing.lines().any(|line| line.trim() == entry || line.trim() == unanchored)
+        });
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut s = String::new();
+        if !existing.is_empty() && !existing.ends_with('\n') {
+            s.push('\n');
+        }
+        s.push_str("# TexCreate build artifacts\n");
+        for entry in entries {
+            s.push_str(&entry);
+            s.push('\n');
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+        file.write_all(s.as_bytes()).await?;
+        // the write only finishes in the background otherwise
+        file.flush().await?;
+        Ok(())
+    }
 
-    async fn output(&self){
-        let _ = Command::new(&self.compiler)
-            .arg("-output-directory=out")
-            .args(&self.flags)
-            .arg(&self.proj_name)
-            .output()
-            .await
-            .expect("Couldn't compile LaTeX document");
+    /// `out_dir` relative to the project directory, `proj_name`, with `/` separators and
+    /// without `.` components, or `None` when it's outside the project or the project itself
+    fn out_dir_in_project(&self) -> Option<String> {
+        let out_dir = if self.out_dir.is_absolute() {
+            let project = std::path::absolute(&self.proj_name).ok()?;
+            self.out_dir.strip_prefix(project).ok()?.to_path_buf()
+        } else {
+            self.out_dir.clone()
+        };
+        let mut components = vec![];
+        for component in out_dir.components() {
+            match component {
+                Component::Normal(name) => components.push(name.to_str()?),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        (!components.is_empty()).then(|| components.join("/"))
+    }
+
+    /// The engine named by `compiler`
+    pub fn engine(&self) -> Engine {
+        Engine::from_name(&self.compiler)
     }
 
-    async fn spawn(&self){
-        let _ = Command::new(&self.compiler)
-            .arg("-output-directory=out")
-            .args(&self.flags)
-            .arg(&self.proj_name)
-            .spawn()
-            .expect("Compiler failed to start")
-            .wait()
+    /// Checks that the configuration is consistent and supported by the engine
+    ///
+    /// This is also done at the start of `compile`.
+    pub fn validate(&self) -> Result<()> {
+        self.job(&CompileOverrides::default()).validate()
+    }
+
+    /// The absolute path of the compiler that runs, such as to record it for reproducibility
+    /// or to spot the wrong one of several installed TeX distributions being picked up
+    ///
+    /// A `compiler` naming a path is looked up directly, otherwise it's searched in `PATH`,
+    /// trying the extensions of `PATHEXT` on Windows. Fails with
+    /// [`CompilerError::CompilerNotFound`] if there's no such executable.
+    pub async fn resolve_engine_path(&self) -> Result<PathBuf> {
+        find_executable(&self.compiler)
             .await
-            .expect("Couldn't compile LaTeX document");
+            .ok_or_else(|| CompilerError::CompilerNotFound(self.compiler.clone()))
+    }
+
+    /// The project's main source file, `proj_name.tex`
+    pub fn source_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.tex", &self.proj_name))
+    }
+
+    /// The document the compiler produces, `out_dir/proj_name.pdf`, or `.dvi` for DVI output
+    pub fn output_path(&self) -> PathBuf {
+        self.job(&CompileOverrides::default()).output_path()
+    }
+
+    /// The file with the extension `ext` the compiler writes for the project, `out_dir/proj_name.ext`
+    ///
+    /// Every path into `out_dir` is built here, so the compiler's `-output-directory`, reading
+    /// the log and cleaning can't disagree on where the files are.
+    fn artifact_path(&self, ext: &str) -> PathBuf {
+        self.out_dir.join(format!("{}.{}", &self.proj_name, ext))
+    }
+
+    /// The source files the output depends on: the source, the `includes`, and with
+    /// `scan_inputs`, every file they `\input` or `\include`, recursively
+    ///
+    /// Directives are resolved relative to the project, like TeX does, adding `.tex` when there's
+    /// no extension. Files that aren't in the project, such as those found through `TEXINPUTS`,
+    /// are skipped, and each file is only read once, so cyclic inputs are fine. Only the `.tex`
+    /// files and those without an extension are scanned, so `includes` can list images.
+    pub async fn dependencies(&self) -> Result<Vec<PathBuf>> {
+        let mut dependencies = vec![self.source_path()];
+        dependencies.extend(self.includes.iter().cloned());
+        if !self.scan_inputs {
+            return Ok(dependencies);
+        }
+        let mut pending = dependencies.clone();
+        let mut seen: HashSet<PathBuf> = dependencies.iter().cloned().collect();
+        while let Some(path) = pending.pop() {
+            if path.extension().is_some_and(|ext| ext != "tex") {
+                continue;
+            }
+            let source = match read_to_string(self.in_work_dir(&path)).await {
+                Ok(source) => source,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    return Err(CompilerError::SourceNotFound(path));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            for input in tex_inputs(&source) {
+                let mut input = PathBuf::from(input);
+                if input.extension().is_none() {
+                    input.set_extension("tex");
+                }
+                if !seen.contains(&input) && metadata(self.in_work_dir(&input)).await.is_ok() {
+                    seen.insert(input.clone());
+                    dependencies.push(input.clone());
+                    pending.push(input);
+                }
+            }
+        }
+        Ok(dependencies)
+    }
+
+    /// Whether the output is missing, or older than any of the [`dependencies`](Self::dependencies)
+    ///
+    /// Fails with [`CompilerError::SourceNotFound`] if the source or one of the `includes`
+    /// doesn't exist.
+    pub async fn is_stale(&self) -> Result<bool> {
+        let mut newest = SystemTime::UNIX_EPOCH;
+        for path in self.dependencies().await? {
+            newest = newest.max(modified(&self.in_work_dir(path)).await?);
+        }
+        match metadata(self.output_path()).await {
+            Ok(meta) => Ok(meta.modified()? < newest),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// A rough estimate of how expensive compiling the project is, for scheduling
+    ///
+    /// This is a heuristic rather than a time estimate: it grows with the size of the
+    /// source and the `includes`, and with the number of passes that run, so it's only meant
+    /// for comparing projects, such as running the likely slowest ones first.
+    pub async fn estimate_cost(&self) -> Result<u64> {
+        // starting the engine and loading the format costs about as much as reading this much source
+        const PASS_OVERHEAD: u64 = 64 * 1024;
+        let mut size = source_len(&self.source_path()).await?;
+        for path in &self.includes {
+            size += source_len(path).await?;
+        }
+        let passes = self.job(&CompileOverrides::default()).passes;
+        Ok((PASS_OVERHEAD + size) * u64::from(passes))
+    }
+
+    /// Creates `out_dir` if it's missing, and checks that it can be written to
+    ///
+    /// This is done at the start of `compile`, so a read-only directory fails with
+    /// [`CompilerError::OutputDirNotWritable`] instead of a confusing error from TeX or cleaning.
+    pub async fn prepare_out_dir(&self) -> Result<()> {
+        let not_writable = |source| CompilerError::OutputDirNotWritable {
+            path: self.out_dir.clone(),
+            source,
+        };
+        create_dir_all(&self.out_dir).await.map_err(not_writable)?;
+        // permissions alone don't tell, as with read-only mounts, so try writing a file
+        let probe = self.out_dir.join(format!(".{}.texc-probe", &self.proj_name));
+        File::create(&probe).await.map_err(not_writable)?;
+        remove_file(&probe).await.map_err(not_writable)?;
+        Ok(())
+    }
+
+    /// The generated file that runs the injected TeX code, then inputs the project's source
+    fn entry_file(&self) -> PathBuf {
+        PathBuf::from(format!("{}.texc.tex", &self.proj_name))
+    }
+
+    /// The arguments given to the compiler, in order
+    pub fn command_args(&self) -> Vec<String> {
+        self.job(&CompileOverrides::default()).command_args()
+    }
+
+    /// The full compile command line, starting with the program that is run
+    ///
+    /// When `wrapper` is set, its first element is the program and the rest are its leading
+    /// arguments, followed by the compiler and its own arguments.
+    pub fn argv(&self) -> Vec<String> {
+        self.job(&CompileOverrides::default()).argv()
+    }
+
+    /// `path` in the directory the compiler runs in
+    fn in_work_dir(&self, path: impl AsRef<Path>) -> PathBuf {
+        match &self.work_dir {
+            Some(work_dir) => work_dir.join(path),
+            None => path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The steps run between passes, starting with `makeindex` when `index` is set
+    ///
+    /// With `index`, `makeindex proj_name.idx` runs after the first pass, before anything else.
+    /// After each pass, the `custom_steps` with that `after_pass` run in the order they're
+    /// listed, inside `out_dir` so they find the files the pass wrote, such as
+    /// `makeglossaries proj_name`. A step that fails to start or exits unsuccessfully stops
+    /// the compile, without cleaning, failing with [`CompilerError::StepFailed`] in the latter
+    /// case.
+    fn steps(&self) -> Vec<CustomStep> {
+        let mut steps = Vec::with_capacity(self.custom_steps.len() + 1);
+        if self.index {
+            steps.push(CustomStep {
+                command: format!("makeindex {}.idx", &self.proj_name),
+                after_pass: 1,
+            });
+        }
+        steps.extend(self.custom_steps.iter().cloned());
+        steps
+    }
+
+    /// The extensions removed when cleaning, adding the `makeindex` files when `index` is set
+    ///
+    /// The SyncTeX file is never removed when `synctex` asks for it, since editors read it
+    /// after the compile.
+    fn clean_exts(&self) -> Vec<&str> {
+        let mut exts: Vec<&str> = self
+            .clean_exts
+            .iter()
+            .map(String::as_str)
+            .filter(|ext| Some(*ext) != self.synctex.ext())
+            .collect();
+        if self.index {
+            for ext in ["idx", "ind", "ilg"] {
+                if !exts.contains(&ext) {
+                    exts.push(ext);
+                }
+            }
+        }
+        exts
+    }
+
+    /// The command line of a custom step, under the same wrappers as the compiler
+    fn step_argv(&self, step: &CustomStep) -> Vec<String> {
+        let mut argv = self.sandbox.as_ref().map(|sandbox| sandbox.wrapper.clone()).unwrap_or_default();
+        argv.extend(self.wrapper.iter().cloned());
+        argv.extend(step.command.split_whitespace().map(str::to_string));
+        argv
+    }
+
+    /// Runs a custom step in `out_dir`, failing if it exits unsuccessfully
+    async fn run_step(&self, step: &CustomStep) -> Result<()> {
+        let argv = self.step_argv(step);
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]).current_dir(&self.out_dir);
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.restrict(&mut command);
+        }
+        if matches!(self.mode, CompilerMode::Output) {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        let status = match ChildGuard::spawn(&mut command) {
+            Ok(mut child) => child.wait().await,
+            Err(e) => Err(e),
+        };
+        let status = status.map_err(|source| CompilerError::Command { argv, source })?;
+        if !status.success() {
+            return Err(CompilerError::StepFailed {
+                command: step.command.clone(),
+                status,
+            });
+        }
+        Ok(())
     }
 
     /// Compiles a TexCreate project
@@ -100,24 +1438,938 @@ impl Compiler {
     /// # using pdflatex as example compiler
     /// $ pdflatex -output-directory=out <flags> `proj_name`.tex
     /// ```
-    pub async fn compile(&self) -> Result<()> {
-        // run the compile command
-        match self.mode{
-            CompilerMode::Spawn => self.spawn().await,
-            CompilerMode::Output => self.output().await
-        }
-        if self.clean{
-            // clean the out directory by removing the aux and log files
-            // should exist if the project compiled successfully
-            let out = PathBuf::from("out");
-            let aux = out.join(format!("{}.aux", &self.proj_name));
-            let log = out.join(format!("{}.log", &self.proj_name));
-            remove_file(aux).await?;
-            remove_file(log).await?;
-        }
-        // if nothing panicked then we have a successful compile
-        cprint!(Green, "The project `{}` successfully compiled!", &self.proj_name);
+    ///
+    /// The command is run `passes` times, with the `steps` in between, and the returned report
+    /// counts the warnings found in the log of the final pass. A compiler that exits
+    /// unsuccessfully fails with [`CompilerError::CompileFailed`], without cleaning, so the log
+    /// is kept.
+    pub async fn compile(&self) -> Result<CompileReport> {
+        self.compile_with(&CompileOverrides::default()).await
+    }
+    /// Compiles the project like `compile`, with `overrides` replacing some of the settings
+    ///
+    /// The overrides are borrowed and the configuration isn't cloned, so a server can reuse
+    /// one base configuration across many compiles with different flags cheaply.
+    pub async fn compile_with(&self, overrides: &CompileOverrides<'_>) -> Result<CompileReport> {
+        let result = self.job(overrides).run(None).await;
+        self.print_error(&result);
+        result
+    }
+    /// Spawns the compile onto the runtime of `handle` rather than the ambient one
+    ///
+    /// The configuration is cloned into the task. A caller that isn't async can wait for
+    /// the returned `JoinHandle` with the runtime's `block_on`.
+    pub fn compile_on(&self, handle: tokio::runtime::Handle) -> tokio::task::JoinHandle<Result<CompileReport>> {
+        let compiler = self.clone();
+        handle.spawn(async move { compiler.compile().await })
+    }
+    /// Compiles the project like `compile`, sending its progress to `events`
+    ///
+    /// The compiler's output is piped regardless of `mode`, so that each line it prints can be
+    /// sent as a [`CompileEvent::LineEmitted`]. A successful compile ends with a
+    /// [`CompileEvent::Finished`] event, while a failed one only returns the error.
+    pub async fn compile_events(&self, events: Sender<CompileEvent>) -> Result<CompileReport> {
+        let result = self.job(&CompileOverrides::default()).run(Some(&events)).await;
+        self.print_error(&result);
+        if let Ok(report) = &result {
+            let _ = events.send(CompileEvent::Finished(report.clone())).await;
+        }
+        result
+    }
+
+    /// Compiles the project like `compile`, sending each new line of its TeX log to `lines`
+    /// as it's written, for a live view of a slow compile
+    ///
+    /// The log is polled every 100 milliseconds rather than piping the compiler's output, so
+    /// this works in `Spawn` mode too. The log of a previous compile isn't sent, and since
+    /// each pass rewrites the log, the remaining lines of a pass are sent when it ends, and
+    /// the next pass's log is followed from its start. When `sandbox` is set, the
+    /// log is only copied back once the compile is done, so every line arrives at the end.
+    pub async fn compile_tailing_log(&self, lines: Sender<String>) -> Result<CompileReport> {
+        let tail = tokio::sync::Mutex::new(LogTail::new(self.artifact_path("log"), lines).await);
+        let overrides = CompileOverrides {
+            clean: Some(false),
+            ..Default::default()
+        };
+        let job = self.job(&overrides).with_log_tail(&tail);
+        let compile = job.run(None);
+        tokio::pin!(compile);
+        let result = loop {
+            tokio::select! {
+                result = &mut compile => break result,
+                _ = tokio::time::sleep(Duration::from_millis(100)) => {
+                    // the compile holds it while a pass starts or ends, which it's then left to
+                    if let Ok(mut tail) = tail.try_lock() {
+                        tail.forward().await;
+                    }
+                }
+            }
+        };
+        self.print_error(&result);
+        // a sandboxed compile only copies the log back once it's done
+        tail.lock().await.end_pass().await;
+        let report = result?;
+        if self.clean {
+            self.clean_artifacts().await?;
+        }
+        Ok(report)
+    }
+
+    fn print_error(&self, result: &Result<CompileReport>) {
+        if let Err(e) = result {
+            cprint!(self, Red, "The project `{}` failed to compile: {}", &self.proj_name, e);
+        }
+    }
+
+    /// The files and directories cleaning removes, sorted so they're removed in the same order
+    /// every time
+    fn clean_targets(&self) -> Vec<PathBuf> {
+        let mut targets: Vec<PathBuf> = self
+            .clean_exts()
+            .iter()
+            .map(|ext| self.artifact_path(ext))
+            .collect();
+        if self.clean_copied {
+            targets.extend(self.copy_before_compile.iter().map(|path| self.out_dir.join(path)));
+        }
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    /// Removes the files of other jobs from `out_dir`, such as the stale PDFs of renamed or
+    /// deleted projects sharing it, returning the removed files, sorted
+    ///
+    /// The files of this project, and of the jobs named in `keep`, are kept, as is any file
+    /// named in `keep`, the copies of `copy_before_compile`, and the `log_file`, which isn't
+    /// the log of a job named after it, such as `texc` for `out/texc.log`. Only the files
+    /// directly in `out_dir` with one of the extensions TeX and its tools write are removed,
+    /// never directories, symlinks or other files, so nothing outside `out_dir` is touched.
+    pub async fn prune_artifacts(&self, keep: &[String]) -> Result<Vec<PathBuf>> {
+        let mut entries = match read_dir(&self.out_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let copies: Vec<PathBuf> = self.copy_before_compile.iter().map(|path| self.out_dir.join(path)).collect();
+        let log_file = match &self.log_file {
+            Some(path) => canonicalize(path).await.ok(),
+            None => None,
+        };
+        let mut pruned = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() || copies.contains(&entry.path()) {
+                continue;
+            }
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let job = ARTIFACT_EXTS.iter().find_map(|ext| {
+                name.strip_suffix(ext)
+                    .and_then(|rest| rest.strip_suffix('.'))
+                    .filter(|job| !job.is_empty())
+            });
+            let Some(job) = job else {
+                continue;
+            };
+            if job == self.proj_name || keep.iter().any(|kept| kept == job || kept == name) {
+                continue;
+            }
+            if log_file.is_some() && canonicalize(entry.path()).await.ok() == log_file {
+                continue;
+            }
+            pruned.push(entry.path());
+        }
+        pruned.sort();
+        for path in &pruned {
+            remove_file(path).await?;
+        }
+        self.print_file_summary("Pruned", &pruned);
+        Ok(pruned)
+    }
+
+    /// Cleans the out directory, by removing the files with the `clean_exts` extensions,
+    /// and the copies of `copy_before_compile` with `clean_copied`
+    ///
+    /// This is the only filesystem work a compile does for cleaning, so without `clean` there's
+    /// none. Only the copies can be directories, so the other files are removed without
+    /// checking what they are first, one call each. The files the document didn't produce,
+    /// such as a `toc` without a table of contents, are skipped, and only the removed ones
+    /// are listed. Fails with [`CompilerError::CleanFailed`] if one can't be removed.
+    async fn clean_artifacts(&self) -> Result<()> {
+        let mut cleaned = vec![];
+        for target in self.clean_targets() {
+            let copied = self.clean_copied
+                && self.copy_before_compile.iter().any(|path| self.out_dir.join(path) == target);
+            let removed = if copied {
+                match metadata(&target).await {
+                    Ok(meta) if meta.is_dir() => remove_dir_all(&target).await,
+                    Ok(_) => remove_file(&target).await,
+                    Err(e) => Err(e),
+                }
+            } else {
+                remove_file(&target).await
+            };
+            match removed {
+                Ok(()) => cleaned.push(target),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(source) => return Err(CompilerError::CleanFailed { path: target, source }),
+            }
+        }
+        self.print_file_summary("Cleaned", &cleaned);
         Ok(())
     }
+
+    /// Prints what was done to which files of `out_dir`, such as `Cleaned 2 files: a.aux, a.log`,
+    /// unless there are none
+    fn print_file_summary(&self, done: &str, paths: &[PathBuf]) {
+        if paths.is_empty() {
+            return;
+        }
+        let names: Vec<String> = paths
+            .iter()
+            .map(|path| path.strip_prefix(&self.out_dir).unwrap_or(path).display().to_string())
+            .collect();
+        let files = if names.len() == 1 { "file" } else { "files" };
+        cprint!(self, Green, "{done} {} {files}: {}", names.len(), names.join(", "));
+    }
+
+    /// Lists the fonts of the compiled PDF with `pdffonts`, to check they're all embedded
+    ///
+    /// Fails with [`CompilerError::ToolNotFound`] if `pdffonts`, from poppler or xpdf,
+    /// isn't installed.
+    pub async fn verify_fonts(&self) -> Result<FontReport> {
+        let output = run_tool("pdffonts", &[self.output_path().as_os_str()]).await?;
+        Ok(FontReport::from_pdffonts(&output))
+    }
+    /// Compiles the project, then compares the PDF with `reference`, for golden-file tests
+    ///
+    /// The page counts are always compared, using `pdfinfo` from poppler or xpdf, which
+    /// fails with [`CompilerError::ToolNotFound`] if it isn't installed. The bytes are only
+    /// compared when `SOURCE_DATE_EPOCH` is set, since otherwise the timestamps and IDs
+    /// embedded in each PDF differ between runs.
+    pub async fn compile_and_diff(&self, reference: impl AsRef<Path>) -> Result<DiffResult> {
+        let reference = reference.as_ref();
+        self.compile().await?;
+        let output = self.output_path();
+        let mut diff = DiffResult {
+            pages: pdf_pages(&output).await?,
+            reference_pages: pdf_pages(reference).await?,
+            identical: None,
+            first_difference: None,
+        };
+        if std::env::var_os("SOURCE_DATE_EPOCH").is_some() {
+            let (output, reference) = (read(&output).await?, read(reference).await?);
+            let first_difference = output
+                .iter()
+                .zip(&reference)
+                .position(|(a, b)| a != b)
+                // one is a prefix of the other
+                .or_else(|| (output.len() != reference.len()).then(|| output.len().min(reference.len())));
+            diff.identical = Some(first_difference.is_none());
+            diff.first_difference = first_difference.map(|offset| offset as u64);
+        }
+        Ok(diff)
+    }
+    /// Turns `Compiler` into Makefile rules that build the project without the crate
+    ///
+    /// The output rule runs every pass with the same command line as `compile`, along with
+    /// the copies, the entry file and the steps between passes, and a `clean` target removes
+    /// the files that cleaning would. The recipes assume a POSIX shell, and paths with
+    /// spaces aren't supported by `make` itself.
+    ///
+    /// The configuration is checked with [`Compiler::validate`] first, so there's no rule for
+    /// one `compile` would reject.
+    pub fn to_makefile_rule(&self) -> Result<String> {
+        let job = self.job(&CompileOverrides::default());
+        job.validate()?;
+        let path_word = |path: &Path| recipe_word(&path.display().to_string());
+        let command = recipe_words(job.argv().iter().map(String::as_str));
+        let out_dir = path_word(&self.out_dir);
+        let entry_file = path_word(&self.entry_file());
+        let mut prerequisites = vec![self.source_path()];
+        prerequisites.extend(self.includes.iter().cloned());
+        let prerequisites = recipe_words(prerequisites.iter().filter_map(|path| path.to_str()));
+
+        let mut s = format!("{}: {prerequisites}\n", path_word(&self.output_path()));
+        s.push_str(&format!("\tmkdir -p {out_dir}\n"));
+        for path in &self.copy_before_compile {
+            let copy = self.out_dir.join(path);
+            let parent = path_word(copy.parent().unwrap_or(&self.out_dir));
+            s.push_str(&format!("\tmkdir -p {parent} && cp -R {} {parent}/\n", path_word(path)));
+        }
+        let pretex = job.pretex();
+        if let Some(pretex) = &pretex {
+            let contents = format!("{pretex}\\input{{{}}}", &self.proj_name);
+            s.push_str(&format!("\tprintf '%s\\n' {} > {entry_file}\n", recipe_words(contents.lines())));
+        }
+        let steps = self.steps();
+        for pass in 1..=job.passes {
+            s.push_str(&format!("\t{command}\n"));
+            for step in steps.iter().filter(|step| step.after_pass == pass) {
+                let step = recipe_words(self.step_argv(step).iter().map(String::as_str));
+                s.push_str(&format!("\tcd {out_dir} && {step}\n"));
+            }
+        }
+        if pretex.is_some() {
+            s.push_str(&format!("\trm -f {entry_file}\n"));
+        }
+
+        let cleaned = self.clean_targets();
+        let clean = format!("\trm -rf {}\n", recipe_words(cleaned.iter().filter_map(|path| path.to_str())));
+        if self.clean && !cleaned.is_empty() {
+            s.push_str(&clean);
+        }
+        s.push_str("\n.PHONY: clean\nclean:\n");
+        if !cleaned.is_empty() {
+            s.push_str(&clean);
+        }
+        Ok(s)
+    }
+    /// Compiles the project, then writes the report to stdout as a single line of JSON
+    ///
+    /// This is meant for wrapping the crate in a subprocess called from other languages, so
+    /// no other message is written, and the compiler's output is captured even in `Spawn`
+    /// mode. A failed compile writes `{"error": "<message>"}` instead, and returns the error,
+    /// so returning it from `main` exits with a non-zero status.
+    #[cfg(feature = "json")]
+    pub async fn compile_json_stdout(&self) -> Result<()> {
+        let mut compiler = self.clone();
+        compiler.quiet = true;
+        // a spawned compiler would write its own output to stdout, before the JSON line
+        compiler.mode = CompilerMode::Output;
+        let result = compiler.compile().await;
+        let line = match &result {
+            Ok(report) => serde_json::to_string(report).expect("a report always serializes"),
+            Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+        };
+        println!("{line}");
+        result.map(|_| ())
+    }
+    /// Compiles the project, then bundles the PDF, the log and the SyncTeX file into a zip
+    /// archive at `zip_path`, such as for handing a build to reviewers
+    ///
+    /// The log is archived before cleaning removes it. In `draft` mode there's no PDF, so
+    /// only the other files are archived.
+    #[cfg(feature = "zip")]
+    pub async fn compile_to_zip(&self, zip_path: impl AsRef<Path>) -> Result<()> {
+        self.compile_with(&CompileOverrides {
+            clean: Some(false),
+            ..Default::default()
+        })
+        .await?;
+        let mut files = vec![];
+        if !self.draft {
+            files.push(self.output_path());
+        }
+        files.push(self.artifact_path("log"));
+        if let Some(ext) = self.synctex.ext() {
+            files.push(self.artifact_path(ext));
+        }
+        let mut entries = vec![];
+        for path in files {
+            match read(&path).await {
+                Ok(contents) => entries.push((path, contents)),
+                // the log and SyncTeX file aren't essential
+                Err(e) if e.kind() == ErrorKind::NotFound && path != self.output_path() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let zip_path = zip_path.as_ref().to_path_buf();
+        tokio::task::spawn_blocking(move || write_zip(&zip_path, entries))
+            .await
+            .expect("writing the zip archive panicked")?;
+        if self.clean {
+            self.clean_artifacts().await?;
+        }
+        Ok(())
+    }
+    /// Compiles the project with the overrides of the profile `name` applied
+    ///
+    /// Fails with [`CompilerError::UnknownProfile`] if `profiles` has no such profile.
+    pub async fn compile_profile(&self, name: &str) -> Result<CompileReport> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| CompilerError::UnknownProfile(name.to_string()))?;
+        self.compile_with(&CompileOverrides {
+            flags: profile.flags.as_deref(),
+            passes: profile.passes,
+            draft: profile.draft,
+            clean: profile.clean,
+        })
+        .await
+    }
+    /// Compiles the document `tex` into a PDF, returned with its warnings and log, such as for
+    /// the response of a rendering service
+    ///
+    /// Nothing persists: `tex` is written as `proj_name.tex` to a fresh temporary directory
+    /// the compile runs in, which is removed afterwards, even if the compile failed, and
+    /// nothing is written to `out_dir` or cleaned. The document is compiled on its own, so it
+    /// can't `\input` the project's files, and `includes`, `scan_inputs` and
+    /// `copy_before_compile` are left out. The rest of the configuration applies as for
+    /// `compile`, including `sandbox`, except for `draft`, since the PDF is needed. A compiler
+    /// that exits unsuccessfully fails with [`CompilerError::CompileFailed`], as `compile` does.
+    pub async fn render(&self, tex: &str) -> Result<RenderResult> {
+        let dir = TempDir::new("render-", &self.proj_name);
+        let mut config = self.clone();
+        config.out_dir = dir.join(DEFAULT_OUT_DIR);
+        config.work_dir = Some(dir.to_path_buf());
+        // the document is compiled on its own, without the project's files
+        config.includes.clear();
+        config.scan_inputs = false;
+        config.copy_before_compile.clear();
+        create_dir_all(&dir).await?;
+        write(config.in_work_dir(config.source_path()), tex).await?;
+        let report = config
+            .compile_with(&CompileOverrides {
+                draft: Some(false),
+                clean: Some(false),
+                ..Default::default()
+            })
+            .await?;
+        Ok(RenderResult {
+            pdf: read(config.output_path()).await?,
+            warnings: report.warnings,
+            log: report.log_contents.unwrap_or_default(),
+        })
+    }
 }
 
+
+/// Checks at compile time that `Compiler` is `Send + Sync` and that the futures of its async
+/// methods are `Send`, so they can be spawned on a multithreaded runtime
+///
+/// The closure is never called, it only has to build.
+const _: fn(&Compiler, Sender<CompileEvent>) = |compiler, events| {
+    fn assert_send<T: Send>(_: T) {}
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Compiler>();
+    assert_send(Compiler::from_file());
+    assert_send(Compiler::new_auto(""));
+    assert_send(Engine::detect());
+    assert_send(Engine::detect_from(&[]));
+    assert_send(compile_all_within(vec![], 1, Duration::ZERO));
+    assert_send(compiler.create_file());
+    assert_send(compiler.create_gitignore());
+    assert_send(compiler.dependencies());
+    assert_send(compiler.is_stale());
+    assert_send(compiler.resolve_engine_path());
+    assert_send(compiler.estimate_cost());
+    assert_send(compiler.prepare_out_dir());
+    assert_send(compiler.prune_artifacts(&[]));
+    assert_send(compiler.render(""));
+    let mut owned = compiler.clone();
+    assert_send(owned.apply_magic_comments());
+    assert_send(compiler.compile());
+    assert_send(compiler.compile_events(events));
+    assert_send(compiler.compile_tailing_log(tokio::sync::mpsc::channel(1).0));
+    assert_send(compiler.verify_fonts());
+    assert_send(compiler.compile_and_diff(""));
+    assert_send(compiler.compile_profile(""));
+    assert_send(compiler.compile_with(&CompileOverrides::default()));
+    assert_send(compiler.watch());
+    #[cfg(feature = "json")]
+    assert_send(compiler.compile_json_stdout());
+    #[cfg(feature = "zip")]
+    assert_send(compiler.compile_to_zip(""));
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh temporary directory for a test, removed by the test itself
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = unique_temp_dir("test-", name);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes an executable shell script named `name` into `dir`, for a fake compiler
+    #[cfg(unix)]
+    pub(crate) fn script(dir: &Path, name: &str, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.display().to_string()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn render_compiles_on_its_own() {
+        let dir = test_dir("render");
+        // writes the PDF and the log where `-output-directory` says
+        let compiler_path = script(
+            &dir,
+            "pdflatex",
+            "for arg; do case $arg in -output-directory=*) out=${arg#*=};; esac; done\n\
+             printf '%%PDF' > \"$out/doc.pdf\"\n\
+             echo 'Overfull \\hbox (1pt too wide) in paragraph' > \"$out/doc.log\"",
+        );
+        let mut compiler = Compiler::new("doc");
+        compiler.compiler = compiler_path;
+        compiler.quiet = true;
+        compiler.out_dir = dir.join("out");
+        // none of these exist, and they'd only be looked for in the project
+        compiler.includes = vec![PathBuf::from("chap.tex")];
+        compiler.scan_inputs = true;
+        compiler.copy_before_compile = vec![PathBuf::from("assets")];
+        compiler.sandbox = Some(SandboxConfig {
+            wrapper: vec![],
+            env: vec!["PATH".to_string()],
+        });
+        let rendered = compiler.render("\\documentclass{article}").await.unwrap();
+        assert_eq!(rendered.pdf, b"%PDF");
+        assert_eq!(rendered.warnings.overfull, 1);
+        assert!(!compiler.out_dir.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dropping_the_render_removes_its_directory() {
+        let dir = test_dir("render-drop");
+        let cwd_file = dir.join("cwd");
+        let compiler_path = script(&dir, "slowtex", &format!("pwd > {}\nexec sleep 30", cwd_file.display()));
+        let mut compiler = Compiler::new("doc");
+        compiler.compiler = compiler_path;
+        compiler.quiet = true;
+        let render = compiler.render("\\documentclass{article}");
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(500), render).await.is_err());
+        let render_dir = std::fs::read_to_string(&cwd_file).unwrap();
+        assert!(!Path::new(render_dir.trim()).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn prune_removes_only_the_artifacts_of_other_jobs() {
+        let dir = test_dir("prune");
+        let out_dir = dir.join("out");
+        std::fs::create_dir_all(out_dir.join("figs")).unwrap();
+        for name in [
+            "doc.pdf", "doc.synctex.gz", "old.pdf", "old.synctex.gz", "kept.log", "named.aux", "figure.pdf",
+            "notes.txt", "texc.log", "figs/plot.pdf", "../outside.pdf",
+        ] {
+            std::fs::write(out_dir.join(name), "").unwrap();
+        }
+        std::os::unix::fs::symlink(dir.join("outside.pdf"), out_dir.join("link.pdf")).unwrap();
+        let mut compiler = Compiler::new("doc");
+        compiler.quiet = true;
+        compiler.out_dir = out_dir.clone();
+        compiler.copy_before_compile = vec![PathBuf::from("figure.pdf"), PathBuf::from("figs")];
+        compiler.log_file = Some(out_dir.join(".").join("texc.log"));
+        let pruned = compiler
+            .prune_artifacts(&["kept".to_string(), "named.aux".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(pruned, [out_dir.join("old.pdf"), out_dir.join("old.synctex.gz")]);
+        let mut left: Vec<_> = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "doc.pdf", "doc.synctex.gz", "figs", "figure.pdf", "kept.log", "link.pdf", "named.aux", "notes.txt",
+                "texc.log",
+            ]
+        );
+        assert!(out_dir.join("figs/plot.pdf").exists() && dir.join("outside.pdf").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tailing_log_sends_only_the_lines_of_this_compile() {
+        let dir = test_dir("tail");
+        let out_dir = dir.join("out");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(out_dir.join("doc.log"), "stale\n").unwrap();
+        // each pass rewrites the log: the first one slowly, the second one at once, with more
+        // than the first one wrote, and the last one without terminating its last line
+        let compiler_path = script(
+            &dir,
+            "slowtex",
+            "for arg; do case $arg in -output-directory=*) out=${arg#*=};; esac; done\n\
+             pass=$(($(cat \"$out/pass\" 2>/dev/null || echo 0) + 1))\n\
+             echo $pass > \"$out/pass\"\n\
+             case $pass in\n\
+               1) printf 'one\\n' > \"$out/doc.log\"; sleep 0.3; printf 'two\\n' >> \"$out/doc.log\"; sleep 0.3;;\n\
+               2) printf 'three-long-line\\nfour\\n' > \"$out/doc.log\";;\n\
+               *) printf 'five\\ntail' > \"$out/doc.log\";;\n\
+             esac",
+        );
+        let mut compiler = Compiler::new("doc");
+        compiler.compiler = compiler_path;
+        compiler.quiet = true;
+        compiler.out_dir = out_dir;
+        compiler.passes = 3;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        compiler.compile_tailing_log(sender).await.unwrap();
+        let mut lines = vec![];
+        while let Ok(line) = receiver.try_recv() {
+            lines.push(line);
+        }
+        assert_eq!(lines, ["one", "two", "three-long-line", "four", "five", "tail"]);
+        assert!(!compiler.artifact_path("log").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dependencies_follow_cyclic_inputs() {
+        let dir = test_dir("inputs");
+        std::fs::write(dir.join("doc.tex"), "\\input{a}\n\\includegraphics{logo.png}").unwrap();
+        std::fs::write(dir.join("a.tex"), "\\input{b}").unwrap();
+        std::fs::write(dir.join("b.tex"), "\\input{a.tex}").unwrap();
+        // not UTF-8, like most images
+        std::fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0xff, 0xfe]).unwrap();
+        let mut compiler = Compiler::new("doc");
+        compiler.work_dir = Some(dir.clone());
+        compiler.out_dir = dir.join("out");
+        compiler.includes = vec![PathBuf::from("logo.png")];
+        compiler.scan_inputs = true;
+        let dependencies = compiler.dependencies().await.unwrap();
+        assert_eq!(dependencies, ["doc.tex", "logo.png", "a.tex", "b.tex"].map(PathBuf::from));
+        assert!(compiler.is_stale().await.unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tex_inputs_skips_comments() {
+        let source = "\\input{intro}\\include{ chapters/one }\n\
+                      % \\input{commented}\n\
+                      50\\% done \\input{after-escape} % \\include{trailing}\n\
+                      \\input{}";
+        assert_eq!(tex_inputs(source), ["intro", "chapters/one", "after-escape"]);
+    }
+
+    #[tokio::test]
+    async fn gitignore_is_appended_to_once() {
+        let dir = test_dir("gitignore");
+        let path = dir.join(".gitignore");
+        std::fs::write(&path, "target/\n*.log").unwrap();
+        // the project's directory is named after it
+        let compiler = Compiler::new(dir.to_str().unwrap());
+        compiler.create_gitignore().await.unwrap();
+        let expected = "target/\n*.log\n# TexCreate build artifacts\n/out/\n*.aux\n";
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        compiler.create_gitignore().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gitignore_anchors_out_dir_to_the_project() {
+        let dir = std::env::temp_dir().join("report");
+        let mut compiler = Compiler::new(dir.to_str().unwrap());
+        for (out_dir, entry) in [
+            (PathBuf::from("./build/./pdf"), Some("build/pdf")),
+            (dir.join("out"), Some("out")),
+            (PathBuf::from("../out"), None),
+            (PathBuf::from("."), None),
+            (std::env::temp_dir().join("out"), None),
+        ] {
+            compiler.out_dir = out_dir;
+            assert_eq!(compiler.out_dir_in_project().as_deref(), entry, "{}", compiler.out_dir.display());
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_skips_files_not_produced() {
+        let dir = test_dir("clean");
+        let mut compiler = Compiler::new("doc");
+        compiler.quiet = true;
+        compiler.out_dir = dir.clone();
+        compiler.clean_exts = vec!["aux".to_string(), "log".to_string(), "toc".to_string()];
+        std::fs::write(dir.join("doc.aux"), "").unwrap();
+        std::fs::write(dir.join("doc.log"), "").unwrap();
+        std::fs::write(dir.join("doc.pdf"), "").unwrap();
+        compiler.clean_artifacts().await.unwrap();
+        let left: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(left, ["doc.pdf"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn read_only_out_dir_is_reported() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("read-only");
+        let out_dir = dir.join("out");
+        std::fs::create_dir(&out_dir).unwrap();
+        std::fs::set_permissions(&out_dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // permissions don't bind root, so the probe would be written
+        let bypassed = std::fs::write(out_dir.join("probe"), "").is_ok();
+        let mut compiler = Compiler::new("doc");
+        compiler.out_dir = out_dir.clone();
+        let result = compiler.prepare_out_dir().await;
+        std::fs::set_permissions(&out_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        if !bypassed {
+            assert!(matches!(result, Err(CompilerError::OutputDirNotWritable { path, .. }) if path == out_dir));
+        }
+    }
+
+    #[tokio::test]
+    async fn unwritable_out_dir_is_reported() {
+        let dir = test_dir("unwritable");
+        std::fs::write(dir.join("file"), "").unwrap();
+        // can't be created under a regular file, whatever the permissions of the user
+        let out_dir = dir.join("file").join("out");
+        let mut compiler = Compiler::new("doc");
+        compiler.out_dir = out_dir.clone();
+        let result = compiler.prepare_out_dir().await;
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(result, Err(CompilerError::OutputDirNotWritable { path, .. }) if path == out_dir));
+    }
+
+    /// The TOML tree of a configuration, to compare configurations, whose tables don't depend
+    /// on the order of the `profiles` map
+    fn toml_value(compiler: &Compiler) -> toml::Value {
+        toml::Value::try_from(compiler).unwrap()
+    }
+
+    #[test]
+    fn minimal_string_round_trips() {
+        let compiler: Compiler = toml::from_str(
+            "proj_name = 'doc'\ncompiler = 'xelatex'\npasses = 2\nwatch_debounce = 500\n\
+             custom_steps = [{ command = 'makeglossaries doc', after_pass = 1 }]\n\
+             sandbox = { wrapper = ['unshare', '--net'], env = ['PATH'] }\n\
+             [profiles.final]\nflags = ['-halt-on-error']\nclean = false\n\
+             [profiles.quick]\ndraft = true",
+        )
+        .unwrap();
+        let minimal = compiler.to_minimal_string();
+        assert!(minimal.starts_with("proj_name = \"doc\"\n"), "{minimal}");
+        assert!(!minimal.contains("out_dir"), "{minimal}");
+        let parsed: Compiler = toml::from_str(&minimal).unwrap();
+        assert_eq!(toml_value(&parsed), toml_value(&compiler));
+    }
+
+    #[test]
+    fn minimal_string_of_a_default_config_is_the_project_name() {
+        assert_eq!(Compiler::new("doc").to_minimal_string(), "proj_name = \"doc\"\n");
+    }
+
+    /// A configuration with every optional field set, so each of them is serialized
+    fn full_config() -> Compiler {
+        toml::from_str(
+            "proj_name = 'doc'\npasses = 2\npreamble = '\\\\usepackage{lmodern}'\npdf_version = [1, 5]\n\
+             output_comment = 'abc'\nlog_file = 'build.log'\n\
+             metadata = { title = 'Report', keywords = ['tex'] }\n\
+             sandbox = { wrapper = ['unshare', '--net'], env = ['PATH'] }\n\
+             custom_steps = [{ command = 'makeglossaries doc', after_pass = 1 }]\n\
+             [profiles.quick]\ndraft = true",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn commented_string_round_trips() {
+        let compiler = full_config();
+        let commented = compiler.to_commented_string();
+        let parsed: Compiler = toml::from_str(&commented).unwrap();
+        assert_eq!(toml_value(&parsed), toml_value(&compiler));
+    }
+
+    #[test]
+    fn every_field_has_a_comment() {
+        let toml::Value::Table(table) = toml_value(&full_config()) else {
+            unreachable!("`Compiler` serializes to a table");
+        };
+        for key in table.keys() {
+            assert!(FIELD_DOCS.iter().any(|(field, _)| field == key), "`{key}` has no comment");
+        }
+        // every field was serialized, and no comment is left without its field
+        assert_eq!(table.len(), FIELD_DOCS.len());
+    }
+
+    #[test]
+    fn needs_shell_escape_matches_requests() {
+        assert!(needs_shell_escape(
+            "! Package minted Error: You must invoke LaTeX with the -shell-escape flag."
+        ));
+        assert!(needs_shell_escape(
+            "Package svg Warning: You didn't enable `shell escape' (or `write18')"
+        ));
+        assert!(needs_shell_escape("Package epstopdf Warning: Shell escape feature is not enabled."));
+    }
+
+    #[test]
+    fn needs_shell_escape_ignores_mentions() {
+        let log = "\\write18 enabled.\n\
+                   restricted \\write18 enabled.\n\
+                   Package: shellesc 2023/07/08 v1.0d unified shell escape interface for LaTeX\n\
+                   ! Undefined control sequence.\n\
+                   l.7 \\usepackage{minted} % needs --shell-escape";
+        assert!(!needs_shell_escape(log));
+    }
+
+    #[test]
+    fn managed_flags_are_recognized() {
+        assert!(is_managed_flag("--synctex=1"));
+        assert!(is_managed_flag("-jobname x"));
+        assert!(is_managed_flag("-interaction=batchmode"));
+        assert!(!is_managed_flag("-halt-on-error"));
+    }
+
+    #[test]
+    fn validate_rejects_managed_flags_with_a_field() {
+        let compiler = Compiler::new("doc").with_flags(vec!["-output-directory=x".to_string()]);
+        assert!(matches!(compiler.validate(), Err(CompilerError::InvalidConfig(_))));
+        // `-interaction` has no field, so it's left to `flags`
+        Compiler::new("doc").with_flags(vec!["-interaction=batchmode".to_string()]).validate().unwrap();
+    }
+
+    fn argv(args: &str) -> Vec<String> {
+        args.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn from_argv_converts_managed_flags() {
+        let compiler = Compiler::from_argv(&argv(
+            "pdflatex -jobname=doc --output-comment=abc -draftmode -synctex=1 -output-directory build \
+             -shell-escape -halt-on-error doc.tex",
+        ))
+        .unwrap();
+        compiler.validate().unwrap();
+        assert_eq!(compiler.output_comment.as_deref(), Some("abc"));
+        assert_eq!(compiler.out_dir, PathBuf::from("build"));
+        assert_eq!(compiler.synctex, SyncTex::Compressed);
+        assert!(compiler.draft && compiler.shell_escape);
+        assert_eq!(compiler.distro, Distribution::TexLive);
+        assert_eq!(compiler.flags, ["-halt-on-error"]);
+        let dotted = Compiler::from_argv(&argv("pdflatex ./doc.tex")).unwrap();
+        assert_eq!(dotted.proj_name, "doc");
+        assert!(Compiler::from_argv(&argv("pdflatex chapters/doc.tex")).is_err());
+        let miktex = Compiler::from_argv(&argv("pdflatex -enable-write18 doc.tex")).unwrap();
+        assert!(miktex.shell_escape);
+        assert_eq!(miktex.distro, Distribution::Miktex);
+        assert_eq!(miktex.argv(), ["pdflatex", "-output-directory=out", "-enable-write18", "doc"]);
+    }
+
+    #[test]
+    fn from_argv_rejects_managed_flags_it_cant_convert() {
+        for args in [
+            "pdflatex -jobname=x doc.tex",
+            "xelatex -draftmode doc.tex",
+            "latex -no-pdf doc.tex",
+            "xelatex -output-format=dvi doc.tex",
+            "tectonic -synctex=-1 doc.tex",
+        ] {
+            assert!(
+                matches!(
+                    Compiler::from_argv(&argv(args)),
+                    Err(CompilerError::InvalidConfig(_) | CompilerError::Unsupported { .. })
+                ),
+                "{args}"
+            );
+        }
+        let compiler = Compiler::from_argv(&argv("xelatex -no-pdf doc.tex")).unwrap();
+        assert!(compiler.draft);
+        compiler.validate().unwrap();
+    }
+
+    #[test]
+    fn magic_comments_reads_the_header() {
+        let source = "% !TEX program = xelatex\n\
+                      %!TeX root = ../main.tex\n\
+                      % !TEX TS-program = lualatex\n\
+                      % TEX program = ignored\n\
+                      \\documentclass{article}";
+        assert_eq!(
+            magic_comments(source),
+            [
+                ("program".to_string(), "xelatex"),
+                ("root".to_string(), "../main.tex"),
+                ("program".to_string(), "lualatex"),
+            ]
+        );
+        let late = format!("{}% !TEX program = xelatex", "\n".repeat(MAGIC_COMMENT_LINES));
+        assert!(magic_comments(&late).is_empty());
+    }
+
+    #[test]
+    fn needs_rerun_matches_requests() {
+        assert!(needs_rerun(
+            "LaTeX Warning: Label(s) may have changed. Rerun to get cross-references right."
+        ));
+        assert!(needs_rerun("LaTeX Warning: There were undefined references. Rerun to get them right."));
+        assert!(needs_rerun(
+            "Package rerunfilecheck Warning: File `report.out' has changed.\n\
+             (rerunfilecheck)                Rerun to get outlines right"
+        ));
+        assert!(needs_rerun("Package biblatex Warning: Please rerun LaTeX."));
+    }
+
+    #[test]
+    fn needs_rerun_ignores_banners() {
+        let log = "Package: rerunfilecheck 2022/07/10 v1.10 Rerun checks for auxiliary files (HO)\n\
+                   Output written on report.pdf (1 page, 1234 bytes).";
+        assert!(!needs_rerun(log));
+    }
+
+    #[test]
+    fn makefile_rule_writes_and_removes_the_entry_file() {
+        let mut compiler = Compiler::new("doc").with_flags(vec![]);
+        compiler.clean = false;
+        compiler.preamble = Some("\\def\\price{it's $5}".to_string());
+        // `$` is doubled for `make`, and `'` ends the shell's quote to be escaped outside of it
+        assert_eq!(
+            compiler.to_makefile_rule().unwrap(),
+            "out/doc.pdf: doc.tex\n\
+             \tmkdir -p out\n\
+             \tprintf '%s\\n' '\\AddToHookNext{class/after}{\\def\\price{it'\\''s $$5}}' '\\input{doc}' > doc.texc.tex\n\
+             \tpdflatex -output-directory=out -jobname=doc doc.texc.tex\n\
+             \trm -f doc.texc.tex\n\
+             \n\
+             .PHONY: clean\n\
+             clean:\n\
+             \trm -rf out/doc.aux out/doc.log\n"
+        );
+    }
+
+    #[test]
+    fn makefile_rule_runs_the_steps_between_passes() {
+        let compiler: Compiler = toml::from_str(
+            "proj_name = 'doc'\nflags = ['-halt-on-error']\npasses = 3\nindex = true\n\
+             custom_steps = [{ command = 'makeglossaries doc', after_pass = 2 }]",
+        )
+        .unwrap();
+        let command = "\tpdflatex -output-directory=out -halt-on-error doc\n";
+        let clean = "\trm -rf out/doc.aux out/doc.idx out/doc.ilg out/doc.ind out/doc.log\n";
+        assert_eq!(
+            compiler.to_makefile_rule().unwrap(),
+            format!(
+                "out/doc.pdf: doc.tex\n\tmkdir -p out\n\
+                 {command}\tcd out && makeindex doc.idx\n\
+                 {command}\tcd out && makeglossaries doc\n\
+                 {command}{clean}\n.PHONY: clean\nclean:\n{clean}"
+            )
+        );
+    }
+
+    #[test]
+    fn makefile_rule_rejects_invalid_configs() {
+        // `makeindex` would run after the last pass, so nothing would read the index
+        let compiler: Compiler = toml::from_str("proj_name = 'doc'\nindex = true").unwrap();
+        assert!(matches!(compiler.to_makefile_rule(), Err(CompilerError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn steps_run_under_the_wrappers() {
+        let compiler: Compiler = toml::from_str(
+            "proj_name = 'doc'\npasses = 2\nindex = true\nwrapper = ['nice']\n\
+             sandbox = { wrapper = ['unshare', '--net'] }",
+        )
+        .unwrap();
+        let rule = compiler.to_makefile_rule().unwrap();
+        assert!(rule.contains("\n\tunshare --net nice pdflatex "), "{rule}");
+        assert!(rule.contains("\n\tcd out && unshare --net nice makeindex doc.idx\n"), "{rule}");
+    }
+}
@@ -0,0 +1,12 @@
+use std::time::Duration;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Writes a `Duration` as a whole number of milliseconds
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+/// Reads a `Duration` from a whole number of milliseconds
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
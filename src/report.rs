@@ -0,0 +1,322 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+/// The outcome of a successful compile
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CompileReport {
+    /// How many times the compiler was run
+    pub passes: u32,
+    /// Whether the log of the final pass still asks for a rerun
+    pub rerun_requested: bool,
+    /// The warnings found in the log of the final pass
+    pub warnings: Warnings,
+    /// The log of the final pass, when the compiler wrote one, read before cleaning removes it
+    pub log_contents: Option<String>,
+}
+
+/// The warnings found in a LaTeX log, counted by category
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Warnings {
+    /// `Overfull \hbox` and `Overfull \vbox` warnings
+    pub overfull: u32,
+    /// `Underfull \hbox` and `Underfull \vbox` warnings
+    pub underfull: u32,
+    /// Undefined reference and citation warnings
+    pub undefined_refs: u32,
+    /// `Missing character` warnings, for glyphs the font doesn't have
+    pub missing_chars: u32,
+    /// Any other warning, such as those emitted by packages
+    pub other: u32,
+}
+
+impl Warnings {
+    /// Counts the warnings in the contents of a LaTeX log
+    pub fn from_log(log: &str) -> Self {
+        let mut warnings = Self::default();
+        for line in log.lines() {
+            if line.starts_with("Overfull \\") {
+                warnings.overfull += 1;
+            } else if line.starts_with("Underfull \\") {
+                warnings.underfull += 1;
+            } else if line.starts_with("Missing character:") {
+                warnings.missing_chars += 1;
+            } else if line.contains("Warning:") {
+                if line.contains("There were undefined references") {
+                    // the summary at the end of the run repeats the individual warnings
+                    continue;
+                }
+                if (line.contains("Reference `") || line.contains("Citation `")) && line.contains("undefined") {
+                    warnings.undefined_refs += 1;
+                } else {
+                    warnings.other += 1;
+                }
+            }
+        }
+        warnings
+    }
+    /// The number of warnings across all categories
+    pub fn total(&self) -> u32 {
+        self.overfull + self.underfull + self.undefined_refs + self.missing_chars + self.other
+    }
+}
+
+/// The progress of a compile, sent by `Compiler::compile_events`
+#[derive(Debug, Clone)]
+pub enum CompileEvent {
+    /// A pass of the compiler started, counting from 1
+    PassStarted(u32),
+    /// The compiler printed a line, on stdout or stderr
+    LineEmitted(String),
+    /// The compile succeeded, with its report
+    Finished(CompileReport),
+}
+
+/// The fonts of a PDF, as listed by `pdffonts`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FontReport {
+    pub fonts: Vec<FontInfo>,
+}
+
+/// A font used by a PDF
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FontInfo {
+    pub name: String,
+    /// Whether the font program is embedded in the PDF
+    pub embedded: bool,
+    /// Whether only the glyphs the document uses are embedded
+    pub subset: bool,
+}
+
+impl FontReport {
+    /// Parses the output of `pdffonts`
+    ///
+    /// Font types and encodings can contain spaces, but names can't, and each line always
+    /// ends with the `emb`, `sub`, `uni`, and two object ID columns, so those are the only
+    /// columns located.
+    pub fn from_pdffonts(output: &str) -> Self {
+        let fonts = output
+            .lines()
+            // the header, and the dashed line under it
+            .skip(2)
+            .filter_map(|line| {
+                let columns: Vec<&str> = line.split_whitespace().collect();
+                let n = columns.len();
+                (n >= 6).then(|| FontInfo {
+                    name: columns[0].to_string(),
+                    embedded: columns[n - 5] == "yes",
+                    subset: columns[n - 4] == "yes",
+                })
+            })
+            .collect();
+        Self { fonts }
+    }
+    /// Whether every font is embedded and subset, as print shops usually require
+    pub fn all_embedded_and_subset(&self) -> bool {
+        self.fonts.iter().all(|font| font.embedded && font.subset)
+    }
+}
+
+/// How a compiled PDF compares to a reference PDF, from `Compiler::compile_and_diff`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DiffResult {
+    /// The page count of the compiled PDF
+    pub pages: u32,
+    /// The page count of the reference PDF
+    pub reference_pages: u32,
+    /// Whether the PDFs are byte-for-byte identical, only checked for reproducible builds
+    pub identical: Option<bool>,
+    /// The offset of the first byte that differs, when they aren't identical
+    pub first_difference: Option<u64>,
+}
+
+impl DiffResult {
+    /// Whether the page counts match, and the bytes too when they were compared
+    pub fn matches(&self) -> bool {
+        self.pages == self.reference_pages && self.identical != Some(false)
+    }
+}
+
+/// A rendered document, from `Compiler::render`
+#[derive(Debug, Clone, Default)]
+pub struct RenderResult {
+    /// The contents of the PDF
+    pub pdf: Vec<u8>,
+    /// The warnings found in the log of the final pass
+    pub warnings: Warnings,
+    /// The log of the final pass, empty if the compiler didn't write one
+    pub log: String,
+}
+
+/// What a notable line of a LaTeX log reports
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LogEntryKind {
+    /// A file the document uses doesn't exist, such as an `\includegraphics` image or a package
+    MissingFile,
+    /// Any other error
+    Error,
+}
+
+/// A notable line of a LaTeX log, from `parse_log`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LogEntry {
+    pub kind: LogEntryKind,
+    /// The file the entry is about, as written in the source, for a `MissingFile`
+    pub path: Option<PathBuf>,
+    /// The line of the log
+    pub message: String,
+}
+
+/// Finds the errors in the contents of a LaTeX log, telling the missing files apart so
+/// tooling can suggest a fix, such as checking the assets directory
+///
+/// The log of a successful compile is kept in its report's `log_contents`, since cleaning
+/// may remove the file.
+///
+/// Missing files are recognized from LaTeX's `File `foo.png' not found`, TeX's
+/// `I can't find file `foo'` and `xelatex`'s `Unable to load picture or PDF file 'foo.png'`.
+/// Errors are the lines starting with `!`, or with `file:line:` under `-file-line-error`.
+pub fn parse_log(log: &str) -> Vec<LogEntry> {
+    log.lines()
+        .filter_map(|line| {
+            if let Some(path) = missing_file(line) {
+                Some(LogEntry {
+                    kind: LogEntryKind::MissingFile,
+                    path: Some(PathBuf::from(path)),
+                    message: line.to_string(),
+                })
+            } else if line.starts_with("! ") || is_file_line_error(line) {
+                Some(LogEntry {
+                    kind: LogEntryKind::Error,
+                    path: None,
+                    message: line.to_string(),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The file a log line says is missing, if it says so
+fn missing_file(line: &str) -> Option<&str> {
+    let quoted = |start: &str, end: char| {
+        let (_, rest) = line.split_once(start)?;
+        rest.split_once(end).map(|(path, _)| path)
+    };
+    if line.contains("' not found") {
+        quoted("File `", '\'')
+    } else if line.contains("I can't find file `") {
+        quoted("I can't find file `", '\'')
+    } else if line.contains("Unable to load picture or PDF file '") {
+        quoted("Unable to load picture or PDF file '", '\'')
+    } else {
+        None
+    }
+}
+
+/// Whether a log line is an error under `-file-line-error`, `file:line: message`
+///
+/// The file can contain colons itself, such as the drive of `C:\doc\report.tex:3: ...` on
+/// Windows, so any `:line: ` after the start of the line counts.
+fn is_file_line_error(line: &str) -> bool {
+    line.match_indices(':').any(|(i, _)| {
+        let rest = &line[i + 1..];
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        i > 0 && digits > 0 && rest[digits..].starts_with(": ")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+This is pdfTeX, Version 3.141592653-2.6-1.40.25 (TeX Live 2023) (preloaded format=pdflatex)
+LaTeX Warning: File `logo.png' not found on input line 12.
+! LaTeX Error: File `missing.sty' not found.
+! I can't find file `chapter2'.
+./report.tex:14: Undefined control sequence.
+C:\\Users\\me\\report.tex:3: Missing $ inserted.
+! Emergency stop.
+Overfull \\hbox (2.0pt too wide) in paragraph at lines 3--4
+Output written on report.pdf (1 page, 1234 bytes).
+";
+
+    #[test]
+    fn parse_log_finds_missing_files_and_errors() {
+        let entries = parse_log(LOG);
+        let missing: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.kind == LogEntryKind::MissingFile)
+            .map(|entry| entry.path.clone().unwrap())
+            .collect();
+        assert_eq!(missing, [PathBuf::from("logo.png"), "missing.sty".into(), "chapter2".into()]);
+        let errors: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.kind == LogEntryKind::Error)
+            .map(|entry| entry.message.as_str())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "./report.tex:14: Undefined control sequence.",
+                "C:\\Users\\me\\report.tex:3: Missing $ inserted.",
+                "! Emergency stop.",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_file_recognizes_xelatex() {
+        let line = "! Unable to load picture or PDF file 'figs/plot.pdf'.";
+        assert_eq!(missing_file(line), Some("figs/plot.pdf"));
+        assert_eq!(missing_file("(./report.aux) (/usr/share/texmf/tex/latex/base/size10.clo)"), None);
+    }
+
+    #[test]
+    fn is_file_line_error_needs_a_line_number() {
+        assert!(is_file_line_error("report.tex:7: Undefined control sequence."));
+        assert!(is_file_line_error("C:\\doc\\report.tex:3: Missing $ inserted."));
+        assert!(!is_file_line_error("Package hyperref Warning: Token not allowed: 3 items"));
+        assert!(!is_file_line_error(":3: no file"));
+        assert!(!is_file_line_error("Output written on report.pdf (1 page, 1234 bytes)."));
+    }
+
+    #[test]
+    fn warnings_from_log_counts_categories() {
+        let log = "\
+Overfull \\hbox (2.0pt too wide) in paragraph
+Underfull \\vbox (badness 10000) has occurred
+LaTeX Warning: Reference `fig:plot' on page 1 undefined on input line 9.
+LaTeX Warning: Citation `knuth' on page 1 undefined on input line 10.
+LaTeX Warning: There were undefined references.
+Missing character: There is no \u{3b1} in font cmr10!
+Package fancyhdr Warning: \\headheight is too small (12.0pt).
+";
+        let warnings = Warnings::from_log(log);
+        assert_eq!(
+            warnings,
+            Warnings { overfull: 1, underfull: 1, undefined_refs: 2, missing_chars: 1, other: 1 }
+        );
+        assert_eq!(warnings.total(), 6);
+    }
+
+    #[test]
+    fn from_pdffonts_reads_the_trailing_columns() {
+        let output = "\
+name                                 type              encoding         emb sub uni object ID
+------------------------------------ ----------------- ---------------- --- --- --- ---------
+ABCDEF+CMR10                         Type 1            Builtin          yes yes no       4  0
+Helvetica                            Type 1            WinAnsi          no  no  no       6  0
+";
+        let report = FontReport::from_pdffonts(output);
+        assert_eq!(
+            report.fonts,
+            [
+                FontInfo { name: "ABCDEF+CMR10".to_string(), embedded: true, subset: true },
+                FontInfo { name: "Helvetica".to_string(), embedded: false, subset: false },
+            ]
+        );
+        assert!(!report.all_embedded_and_subset());
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs::read_dir;
+use tokio::time::sleep;
+use crate::{Compiler, Result};
+
+/// How often `watch` checks the project for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The directories of version control systems, which `watch` never looks in, so a commit or
+/// a checkout doesn't recompile
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".bzr", ".jj"];
+
+/// The modification time of every file `watch` looks at
+type Snapshot = HashMap<PathBuf, SystemTime>;
+
+impl Compiler {
+    /// Compiles the project, then again every time one of its files changes, until the
+    /// future is dropped, such as when a `tokio::signal::ctrl_c` in a `select!` completes
+    ///
+    /// Every file under the current directory is watched, except `out_dir`, the `log_file`
+    /// and the files matching `watch_ignore`, so the compile's own output doesn't trigger
+    /// another one, and the directories of version control systems, such as `.git`. A change
+    /// only recompiles once nothing has changed for `watch_debounce`, so an editor that saves
+    /// through a temporary file or auto-saves rapidly triggers a single compile. A failed
+    /// compile prints its error and keeps watching.
+    pub async fn watch(&self) -> Result<()> {
+        let _ = self.compile().await;
+        let mut last = self.snapshot().await?;
+        loop {
+            sleep(POLL_INTERVAL).await;
+            let mut current = self.snapshot().await?;
+            if current == last {
+                continue;
+            }
+            // wait for the files to stay unchanged for the whole interval
+            loop {
+                sleep(self.watch_debounce).await;
+                let settled = self.snapshot().await?;
+                if settled == current {
+                    break;
+                }
+                current = settled;
+            }
+            let _ = self.compile().await;
+            // changes made while compiling are picked up by the next snapshot
+            last = current;
+        }
+    }
+
+    /// The modification times of the watched files
+    async fn snapshot(&self) -> Result<Snapshot> {
+        let current_dir = std::env::current_dir()?;
+        let ignored_paths = [
+            Some(normalize(&self.out_dir, &current_dir)),
+            self.log_file.as_deref().map(|path| normalize(path, &current_dir)),
+            Some(self.entry_file()),
+        ];
+        let mut snapshot = Snapshot::new();
+        let mut pending = vec![PathBuf::new()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { &dir }).await {
+                Ok(entries) => entries,
+                // removed since it was listed
+                Err(_) if !dir.as_os_str().is_empty() => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = dir.join(entry.file_name());
+                if ignored_paths.iter().flatten().any(|ignored| *ignored == path) || self.is_watch_ignored(&path) {
+                    continue;
+                }
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    pending.push(path);
+                } else if let Ok(modified) = metadata.modified() {
+                    snapshot.insert(path, modified);
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Whether `path`, relative to the current directory, is one of the [`VCS_DIRS`] or
+    /// matches one of `watch_ignore`
+    ///
+    /// A pattern without a `/` matches the file name in any directory, like in `.gitignore`.
+    fn is_watch_ignored(&self, path: &Path) -> bool {
+        let path = path
+            .components()
+            .filter_map(|component| component.as_os_str().to_str())
+            .collect::<Vec<_>>()
+            .join("/");
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        if VCS_DIRS.contains(&name) {
+            return true;
+        }
+        self.watch_ignore.iter().any(|pattern| {
+            let text = if pattern.contains('/') { path.as_str() } else { name };
+            glob_match(pattern.as_bytes(), text.as_bytes())
+        })
+    }
+}
+
+/// `path` relative to `current_dir` when it's inside it, and without its `.` components, to
+/// compare it with the paths `snapshot` lists
+fn normalize(path: &Path, current_dir: &Path) -> PathBuf {
+    path.strip_prefix(current_dir)
+        .unwrap_or(path)
+        .components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+/// Whether `text` matches the glob `pattern`, where `*` and `?` don't match a `/`, and `**`
+/// matches across directories
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) if pattern.starts_with(b"**") => {
+            let (rest, whole_dirs) = match pattern[2..].strip_prefix(b"/") {
+                Some(rest) => (rest, true),
+                None => (&pattern[2..], false),
+            };
+            (0..=text.len())
+                .filter(|&i| !whole_dirs || i == 0 || text[i - 1] == b'/')
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+        (Some(b'*'), _) => {
+            glob_match(&pattern[1..], text)
+                || (text.first().is_some_and(|c| *c != b'/') && glob_match(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(c)) if *c != b'/' => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(c)) if p == c => glob_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_makes_paths_relative_to_the_project() {
+        let project = Path::new("/home/me/report");
+        assert_eq!(normalize(Path::new("/home/me/report/out"), project), Path::new("out"));
+        assert_eq!(normalize(Path::new("./build/./logs"), project), Path::new("build/logs"));
+        assert_eq!(normalize(Path::new("/tmp/out"), project), Path::new("/tmp/out"));
+    }
+
+    #[test]
+    fn glob_match_patterns() {
+        let matches = |pattern: &str, text: &str| glob_match(pattern.as_bytes(), text.as_bytes());
+        assert!(matches("*.swp", ".report.tex.swp"));
+        assert!(matches("chap?.tex", "chap1.tex"));
+        assert!(!matches("chap?.tex", "chap10.tex"));
+        assert!(!matches("*.tex", "chapters/one.tex"));
+        assert!(matches("chapters/*.tex", "chapters/one.tex"));
+        assert!(matches("**/*.bak", "a/b/c.bak"));
+        assert!(matches("**/*.bak", "c.bak"));
+        assert!(matches("build/**", "build/x/y"));
+        assert!(!matches("**/*.bak", "c.bak~"));
+    }
+
+    #[test]
+    fn watch_ignore_matches_names_anywhere() {
+        let mut compiler = Compiler::new("doc");
+        compiler.watch_ignore = vec!["*~".to_string(), "figs/*.svg".to_string()];
+        assert!(compiler.is_watch_ignored(Path::new("chapters/one.tex~")));
+        assert!(compiler.is_watch_ignored(Path::new("figs/plot.svg")));
+        assert!(!compiler.is_watch_ignored(Path::new("other/figs/plot.svg")));
+        assert!(!compiler.is_watch_ignored(Path::new("doc.tex")));
+    }
+
+    #[test]
+    fn watch_ignores_vcs_dirs() {
+        let compiler = Compiler::new("doc");
+        assert!(compiler.is_watch_ignored(Path::new(".git")));
+        assert!(compiler.is_watch_ignored(Path::new("vendor/lib/.hg")));
+        assert!(!compiler.is_watch_ignored(Path::new(".gitignore")));
+    }
+}